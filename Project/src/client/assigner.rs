@@ -0,0 +1,128 @@
+use crate::prelude::*;
+use crate::config::Config;
+use crate::client::cost::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+// The status and current destinations of every unit that can take orders, sorted by unit id
+pub type Units = BTreeMap<u8, (Status, HashSet<Order>)>;
+
+// Decides which unit serves each new hall order. The master remembers the answer and only moves an order
+// to another unit when the first one can't serve it, so an assigner only sees orders that have no unit yet.
+// The destinations in units include the orders given out earlier.
+//
+// Assigners are deterministic: the same orders and units always give the same assignment. Ties are broken
+// by the unit with the fewest destinations, and then by the lowest id.
+pub trait Assigner: Send {
+    fn assign(&mut self, orders: &BTreeSet<Order>, units: &Units) -> BTreeMap<Order, u8>;
+}
+
+// The assignment strategies that can be chosen in the config
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignerKind {
+    TimeToIdle,
+    NearestCar,
+    RoundRobin,
+    Zoning,
+}
+
+impl std::str::FromStr for AssignerKind {
+    type Err = anyhow::Error;
+    fn from_str(name: &str) -> anyhow::Result<AssignerKind> {
+        match name {
+            "time_to_idle" => Ok(AssignerKind::TimeToIdle),
+            "nearest_car" => Ok(AssignerKind::NearestCar),
+            "round_robin" => Ok(AssignerKind::RoundRobin),
+            "zoning" => Ok(AssignerKind::Zoning),
+            other => Err(anyhow!("Unknown assigner {}", other)),
+        }
+    }
+}
+
+// Builds the assigner chosen in the config
+pub fn new_assigner(config: &Config) -> Box<dyn Assigner> {
+    match config.assigner {
+        AssignerKind::TimeToIdle => Box::new(TimeToIdle {
+            timing: CostTiming {
+                travel_time: config.travel_time(),
+                door_open_time: config.door_open_time(),
+            }
+        }),
+        AssignerKind::NearestCar => Box::new(NearestCar),
+        AssignerKind::RoundRobin => Box::new(RoundRobin::default()),
+        AssignerKind::Zoning => Box::new(Zoning { num_floors: config.num_floors }),
+    }
+}
+
+// Simulates the route of every elevator and gives the orders to the units that get everything done soonest
+pub struct TimeToIdle {
+    pub timing: CostTiming,
+}
+
+impl Assigner for TimeToIdle {
+    fn assign(&mut self, orders: &BTreeSet<Order>, units: &Units) -> BTreeMap<Order, u8> {
+        assign_orders(orders, units, self.timing)
+    }
+}
+
+// Gives each order to the elevator closest to its floor
+pub struct NearestCar;
+
+impl Assigner for NearestCar {
+    fn assign(&mut self, orders: &BTreeSet<Order>, units: &Units) -> BTreeMap<Order, u8> {
+        let mut assignment = BTreeMap::new();
+        for order in orders {
+            let nearest = units.iter().min_by_key(|(id, (status, destinations))| {
+                let distance = (i32::from(status.last_floor) - i32::from(order.floor_number)).abs();
+                (distance, destinations.len(), **id)
+            });
+            if let Some((id, _)) = nearest {
+                assignment.insert(*order, *id);
+            }
+        }
+        return assignment;
+    }
+}
+
+// Gives new orders to the units in turn, in order of id
+#[derive(Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl Assigner for RoundRobin {
+    fn assign(&mut self, orders: &BTreeSet<Order>, units: &Units) -> BTreeMap<Order, u8> {
+        let mut assignment = BTreeMap::new();
+        let ids: Vec<u8> = units.keys().copied().collect();
+        if ids.is_empty() {
+            return assignment;
+        }
+        for order in orders {
+            assignment.insert(*order, ids[self.next % ids.len()]);
+            self.next += 1;
+        }
+        return assignment;
+    }
+}
+
+// Splits the shaft into one band of floors per unit, and gives each order to the unit whose band it is in.
+// Keeps the elevators spread out when many calls come at once.
+pub struct Zoning {
+    pub num_floors: u8,
+}
+
+impl Assigner for Zoning {
+    fn assign(&mut self, orders: &BTreeSet<Order>, units: &Units) -> BTreeMap<Order, u8> {
+        let mut assignment = BTreeMap::new();
+        let ids: Vec<u8> = units.keys().copied().collect();
+        if ids.is_empty() {
+            return assignment;
+        }
+        let zone_size = max(1, (self.num_floors as usize).div_ceil(ids.len()));
+        for order in orders {
+            let zone = min(order.floor_number as usize / zone_size, ids.len() - 1);
+            assignment.insert(*order, ids[zone]);
+        }
+        return assignment;
+    }
+}
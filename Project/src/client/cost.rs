@@ -0,0 +1,151 @@
+use crate::prelude::*;
+use crate::client::assigner::Units;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Most floors the route simulation moves through, in case a status makes no sense
+const MAX_STEPS: usize = 1000;
+
+// The timings the route of an elevator is simulated with
+#[derive(Clone, Copy, Debug)]
+pub struct CostTiming {
+    pub travel_time: Duration,
+    pub door_open_time: Duration,
+}
+
+// The elevator itself follows the same rules as the route simulation, see state_machine.rs
+
+pub fn any_above(destinations: &HashSet<Order>, floor: i32) -> bool {
+    destinations.iter().any(|d| i32::from(d.floor_number) > floor)
+}
+
+pub fn any_below(destinations: &HashSet<Order>, floor: i32) -> bool {
+    destinations.iter().any(|d| i32::from(d.floor_number) < floor)
+}
+
+pub fn any_here(destinations: &HashSet<Order>, floor: i32) -> bool {
+    destinations.iter().any(|d| i32::from(d.floor_number) == floor)
+}
+
+pub fn any_ahead(destinations: &HashSet<Order>, floor: i32, dirn: u8) -> bool {
+    match dirn {
+        e::DIRN_UP => any_above(destinations, floor),
+        e::DIRN_DOWN => any_below(destinations, floor),
+        _ => false,
+    }
+}
+
+fn step(dirn: u8) -> i32 {
+    match dirn {
+        e::DIRN_UP => 1,
+        e::DIRN_DOWN => -1,
+        _ => 0,
+    }
+}
+
+// Stop for cab orders, hall calls in our direction, and where there is nothing more ahead
+pub fn should_stop(destinations: &HashSet<Order>, floor: i32, dirn: u8) -> bool {
+    let for_us = destinations.iter().any(|d| {
+        i32::from(d.floor_number) == floor && (d.is_cab() || d.call.direction() == Some(dirn))
+    });
+    return for_us || !any_ahead(destinations, floor, dirn);
+}
+
+// Removes the destinations served by stopping here. Cab orders are always served, hall calls in the
+// other direction only if we are about to turn around.
+pub fn clear_at_floor(destinations: &mut HashSet<Order>, floor: i32, dirn: u8) {
+    let turning = !any_ahead(destinations, floor, dirn);
+    destinations.retain(|d| {
+        i32::from(d.floor_number) != floor || (!turning && !d.is_cab() && d.call.direction() != Some(dirn))
+    });
+}
+
+// Keep going while there are destinations ahead, otherwise turn around
+pub fn choose_direction(destinations: &HashSet<Order>, floor: i32, dirn: u8) -> u8 {
+    if any_ahead(destinations, floor, dirn) {
+        return dirn;
+    }
+    if any_above(destinations, floor) {
+        return e::DIRN_UP;
+    }
+    if any_below(destinations, floor) {
+        return e::DIRN_DOWN;
+    }
+    return e::DIRN_STOP;
+}
+
+// Simulates the route of an elevator through its destinations and returns how long it takes until it is idle,
+// counting travel time between floors and door time at every stop
+pub fn time_to_idle(status: &Status, mut destinations: HashSet<Order>, timing: CostTiming) -> Duration {
+    let mut duration = Duration::ZERO;
+    let mut floor = i32::from(status.last_floor);
+    let mut dirn = status.direction;
+
+    // A moving elevator is on average half way to the next floor
+    if any_ahead(&destinations, floor, dirn) {
+        floor += step(dirn);
+        duration += timing.travel_time / 2;
+    } else {
+        dirn = e::DIRN_STOP;
+    }
+
+    for _ in 0..MAX_STEPS {
+        if dirn == e::DIRN_STOP || should_stop(&destinations, floor, dirn) {
+            if any_here(&destinations, floor) {
+                clear_at_floor(&mut destinations, floor, dirn);
+                duration += timing.door_open_time;
+            }
+            dirn = choose_direction(&destinations, floor, dirn);
+            if dirn == e::DIRN_STOP {
+                if destinations.is_empty() {
+                    return duration;
+                }
+                continue; // What is left is at this floor
+            }
+        }
+        floor += step(dirn);
+        duration += timing.travel_time;
+    }
+    return duration;
+}
+
+// Gives every order to one of the units, keeping the sum of their times to idle as low as possible.
+// The orders are placed one at a time, each time choosing the order and unit that add the least time
+// to the routes built so far, so orders that come in together are spread out together.
+// Ties go to the unit with the fewest destinations, then to the lowest id, then to the lowest order.
+pub fn assign_orders(orders: &BTreeSet<Order>, units: &Units, timing: CostTiming) -> BTreeMap<Order, u8> {
+    let mut assignment: BTreeMap<Order, u8> = BTreeMap::new();
+    if units.is_empty() {
+        return assignment;
+    }
+
+    let mut routes: BTreeMap<u8, HashSet<Order>> = BTreeMap::new();
+    let mut times: BTreeMap<u8, Duration> = BTreeMap::new();
+    for (id, (status, destinations)) in units {
+        routes.insert(*id, destinations.clone());
+        times.insert(*id, time_to_idle(status, destinations.clone(), timing));
+    }
+
+    let mut remaining: BTreeSet<Order> = orders.clone();
+    while !remaining.is_empty() {
+        // The cheapest order and unit to place next, compared as (added time, destinations, unit, order)
+        let mut best: Option<(Duration, usize, u8, Order)> = None;
+        for order in &remaining {
+            for (id, (status, _)) in units {
+                let mut route = routes[id].clone();
+                route.insert(*order);
+                let added = time_to_idle(status, route, timing).saturating_sub(times[id]);
+                let candidate = (added, routes[id].len(), *id, *order);
+                if best.is_none_or(|best| candidate < best) {
+                    best = Some(candidate);
+                }
+            }
+        }
+        let (_, _, id, order) = best.unwrap();
+        remaining.remove(&order);
+        let route = routes.get_mut(&id).unwrap();
+        route.insert(order);
+        times.insert(id, time_to_idle(&units[&id].0, route.clone(), timing));
+        assignment.insert(order, id);
+    }
+    return assignment;
+}
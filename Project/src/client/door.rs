@@ -0,0 +1,81 @@
+use crate::prelude::*;
+
+// How long the door takes to close once the timer has run out. An obstruction in this time opens it again.
+const DOOR_CLOSING_TIME: Duration = Duration::from_millis(200);
+
+// The states of the door
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum DoorState {
+    Closed,
+    Open,
+    Closing,
+}
+
+// The door timer. It does no I/O itself, the elevator state machine turns the light on and off when it is told to.
+#[derive(Clone, Debug)]
+pub struct Door {
+    pub state: DoorState,
+    open_time: Duration,
+    deadline: Instant,
+    obstructed_since: Option<Instant>,
+}
+
+impl Door {
+    pub fn new(open_time: Duration, now: Instant) -> Self {
+        Door {
+            state: DoorState::Closed,
+            open_time,
+            deadline: now,
+            obstructed_since: None,
+        }
+    }
+
+    // Opens the door, or keeps it open for another door time if it already is.
+    // Returns true if the light has to be turned on.
+    pub fn open(&mut self, now: Instant) -> bool {
+        let turn_on = self.state != DoorState::Open;
+        self.state = DoorState::Open;
+        self.deadline = now + self.open_time;
+        return turn_on;
+    }
+
+    // Runs the timer. keep_open starts it over, for as long as the obstruction switch is on or the stop button is pressed,
+    // and opens a closing door again. Returns the new state of the light if it changes.
+    pub fn update(&mut self, now: Instant, keep_open: bool) -> Option<bool> {
+        match self.state {
+            DoorState::Open => {
+                if keep_open {
+                    self.deadline = now + self.open_time;
+                }
+                if now >= self.deadline {
+                    self.state = DoorState::Closing;
+                    self.deadline = now + DOOR_CLOSING_TIME;
+                    return Some(false);
+                }
+            }
+            DoorState::Closing => {
+                if keep_open {
+                    self.state = DoorState::Open;
+                    self.deadline = now + self.open_time;
+                    return Some(true);
+                }
+                if now >= self.deadline {
+                    self.state = DoorState::Closed;
+                }
+            }
+            DoorState::Closed => {}
+        }
+        return None;
+    }
+
+    // Whether the obstruction switch has kept the door open for twice the door time.
+    // The switch only matters while it holds the door open.
+    pub fn prolonged_obstruction(&mut self, now: Instant, obstructed: bool) -> bool {
+        if obstructed && self.state != DoorState::Closed {
+            self.obstructed_since.get_or_insert(now);
+        } else {
+            self.obstructed_since = None;
+        }
+        return self.obstructed_since.is_some_and(|since| now.duration_since(since) >= 2 * self.open_time);
+    }
+}
@@ -1,322 +1,202 @@
 use crate::prelude::*;
 use crate::client::utils::*;
+use crate::client::journal::*;
+use crate::process_pair::PairState;
+use crate::client::hardware::*;
+use crate::client::state_machine::*;
+use crate::client::door::DoorState;
+use crate::clock::Clock;
 
-// When a new foor is passed checks whether we should stop and open the door, then checks whether we should continue
-fn floor_recieved(
-    floor: u8,
-    last_floor: u8,
-    elevator: Elevator,
-    elev_num_floors: u8,
-    internal_order_channel_tx: Sender<InternalCommunication>,
-    elevator_controller_tx: Sender<u8>,
-    elevator_readout_rx: Receiver<u8>,
-    destination_list_rx: Receiver<HashSet<Order>>
-) -> () {
-                println!("Floor: {:#?}", floor);
-                let destination_request = InternalCommunication {
-                    intention: REQUEST_DESTINATION,
-                    order: None
-                };
-                internal_order_channel_tx.send(destination_request).unwrap();
-                let destination_list = destination_list_rx.recv().unwrap();
-
-                let direction_request = InternalCommunication {
-                    intention: REQUEST_DIRECTION,
-                    order: None
-                };
-                internal_order_channel_tx.send(direction_request).unwrap();
-                let dirn: u8 = elevator_readout_rx.recv().unwrap();
-                
-                // println!("Mottat retning: {:#?}", dirn);
-                // println!("Last floor updated to: {:#?}", last_floor);
-                {
-                let elevator_controller_tx = elevator_controller_tx.clone();
-                check_for_bottom(dirn, floor, elev_num_floors, elevator_controller_tx);
-                }
-                
-                // First we find our target floor
-                let destination_list_copy = destination_list.clone();
-                let target_floor = target_floor_function(dirn, destination_list_copy, last_floor).unwrap_or(floor);
-
-                // Secondly we find our heading after the current floor; up, down, or stop
-                let heading = check_continue_or_not(dirn, floor, target_floor);
-
-                if !destination_list.is_empty() {
-                    let destination_list = destination_list.clone();
-                    let elevator_controller_tx = elevator_controller_tx.clone();
-                    let internal_order_channel_tx = internal_order_channel_tx.clone();
-                    if check_for_stop(floor, dirn, destination_list, target_floor, internal_order_channel_tx, elevator_controller_tx) {
-                        // Open the door during temp_stop and check lights
-                        elevator.door_light(true);
-                        check_lights(&elevator, heading, floor, elev_num_floors);
-                        sleep(Duration::from_millis(3000));
-                        elevator.door_light(false);
-                    }
-                }
-                elevator_controller_tx.send(heading).unwrap();
+// The parts of the state machine that are logged when they change
+#[derive(PartialEq, Copy, Clone)]
+struct Logged {
+    behaviour: Behaviour,
+    floor: Option<u8>,
+    obstructed: bool,
+    door: DoorState,
 }
 
-// Check if we're at the bottom of the elevator
-fn check_for_bottom(dirn: u8, floor: u8, elev_num_floors: u8, elevator_controller_tx: Sender<u8>) -> () {
-    println!("Retning: {:#?}", dirn);
-    println!("Etasje: {:#?}", floor);
-    if (dirn == e::DIRN_UP && floor == (elev_num_floors-1))
-    || (dirn == e::DIRN_DOWN && floor == 0) {
-        elevator_controller_tx.send(e::DIRN_STOP).unwrap();
-        println!("CHECKFORBOTTOM stopping");
+impl Logged {
+    fn of(state_machine: &ElevatorStateMachine) -> Self {
+        Logged {
+            behaviour: state_machine.behaviour,
+            floor: state_machine.floor,
+            obstructed: state_machine.obstructed,
+            door: state_machine.door_state(),
+        }
     }
 }
 
-// Returns the next heading
-fn check_continue_or_not(dirn: u8, floor: u8, target_floor: u8) -> u8 {
-    // print!("\nCHECK_CONTINUE\n");
-    // print!("dirn  {:#?}\n",dirn);
-    // print!("floor  {:#?}\n",floor);
-    // print!("target_floor  {:#?}\n",target_floor);
-    if (dirn == e::DIRN_UP && floor < target_floor) || (dirn == e::DIRN_DOWN && floor > target_floor) {
-        return dirn;
+// Prints what an event changed, as the state machine prints nothing itself
+fn log_changes(before: Logged, after: Logged) -> () {
+    if before == after {
+        return;
+    }
+    if let (Some(floor), true) = (after.floor, before.floor != after.floor) {
+        println!("Floor: {:#?}", floor);
+    }
+    if before.behaviour != after.behaviour {
+        match (before.behaviour, after.behaviour) {
+            (_, Behaviour::Fault(error)) => println!("Feil oppdaget: {:?}", error),
+            (Behaviour::Fault(error), _) => println!("Feilen er borte: {:?}", error),
+            (_, Behaviour::Stopped) => println!("Stoppknappen er trykket"),
+            (Behaviour::Stopped, _) => println!("Stoppknappen er sluppet"),
+            _ => {}
+        }
+    }
+    if before.obstructed != after.obstructed {
+        println!("Obstruert over lang tid: {}", after.obstructed);
+    }
+    if before.door != after.door {
+        match (before.door, after.door) {
+            (DoorState::Closing, DoorState::Open) => println!("Døren åpnes igjen"),
+            (_, DoorState::Open) => println!("Døren åpnes"),
+            (_, DoorState::Closed) => println!("Døren er lukket"),
+            _ => {}
+        }
     }
-    println!("CHECKCONTINUE stopping");
-    return e::DIRN_STOP;
 }
 
-// Check if we need to stop
-fn check_for_stop(
-    floor: u8,
-    dirn: u8,
-    destination_list: HashSet<Order>,
-    target_floor: u8,
-    internal_order_channel_tx: Sender<InternalCommunication>,
-    elevator_controller_tx: Sender<u8>
-) -> bool {
-    for destination in destination_list {
-        if destination.floor_number == floor {
-            if (halldirn_to_elevdirn(destination.direction) == dirn) || (floor == target_floor) {
-                elevator_controller_tx.send(DIRN_STOP_TEMP).unwrap();
-                println!("CHECKFORSTOP stopping");
-
-                let new_comm = InternalCommunication {
-                    intention: DELETE,
-                    order: Some(destination)
-                };
-                internal_order_channel_tx.send(new_comm).unwrap();
-
-                return true;
+// Sets the hall lights to the ones the master has confirmed, only touching the buttons that changed.
+// Returns false if the hardware failed, and then hall_lights is left as it was.
+fn update_hall_lights<H: ElevatorHardware>(elevator: &H, hall_lights: &mut Destinations, new_lights: Destinations, num_floors: u8) -> bool {
+    let written = try_hardware(|| {
+        for floor in 0..min(num_floors, Destinations::MAX_FLOORS) {
+            let bit = 1 << floor;
+            if (hall_lights.up ^ new_lights.up) & bit != 0 {
+                elevator.call_button_light(floor, e::HALL_UP, new_lights.up & bit != 0);
+            }
+            if (hall_lights.down ^ new_lights.down) & bit != 0 {
+                elevator.call_button_light(floor, e::HALL_DOWN, new_lights.down & bit != 0);
             }
         }
+    });
+    if written.is_none() {
+        return false;
     }
-    return false;
+    *hall_lights = new_lights;
+    return true;
 }
 
-// Turns off the correct lights based on the elevator floor and direction
-fn check_lights(elevator: &Elevator, dirn: u8, floor: u8, num_floors: u8) -> () {
-    elevator.call_button_light(floor, e::CAB, false);
-    if (dirn == e::DIRN_DOWN) || (floor == (num_floors-1)) {
-        elevator.call_button_light(floor, e::HALL_DOWN, false);
-    }
-    else if (dirn == e::DIRN_UP) || (floor == 0) {
-        elevator.call_button_light(floor, e::HALL_UP, false);
-    }
-    else if dirn == e::DIRN_STOP {
-        elevator.call_button_light(floor, e::HALL_DOWN, false);
-        elevator.call_button_light(floor, e::HALL_UP, false);
+// Shows the hall lights the master wants, unless the hardware has failed. They are all written again once it is back.
+// Returns false if the hardware failed.
+fn show_hall_lights<H: ElevatorHardware>(elevator: &H, hall_lights: &mut Destinations, wanted: Destinations, num_floors: u8, hardware_failed: bool) -> bool {
+    return hardware_failed || update_hall_lights(elevator, hall_lights, wanted, num_floors);
+}
+
+// Tells the state machine the hardware has failed, the first time it happens
+fn driver_failed(state_machine: &mut ElevatorStateMachine, hardware_failed: &mut bool, now: Instant) -> Vec<ElevatorAction> {
+    if *hardware_failed {
+        return Vec::new();
     }
+    println!("Mistet kontakten med heisen");
+    *hardware_failed = true;
+    return state_machine.handle(ElevatorEvent::DriverFailure(true), now);
 }
 
-// Handles cab orders.
-fn handle_cab_order (call_button: CallButton, last_floor: u8, elevator: Elevator, internal_order_channel_tx: Sender<InternalCommunication>) -> () {
-    if call_button.floor < last_floor {
-        let new_order = Order {
-            floor_number: call_button.floor,
-            direction: e::HALL_DOWN
-        };
-        let new_comm = InternalCommunication {
-            intention: INSERT,
-            order: Some(new_order)
-        };
-        internal_order_channel_tx.send(new_comm).unwrap();
-        elevator.call_button_light(call_button.floor, call_button.call, true);
+// Connects to the hardware again after a failure. Once it can be read, the state machine writes everything again,
+// and is given the floor we are at so the fault clears without waiting for the elevator to move.
+fn reconnect<H: ElevatorHardware>(elevator: &H, state_machine: &mut ElevatorStateMachine, now: Instant) -> Option<Vec<ElevatorAction>> {
+    if !elevator.reconnect() {
+        return None;
     }
-    else if call_button.floor >= last_floor {
-        let new_order = Order {
-            floor_number: call_button.floor,
-            direction: e::HALL_UP
-        };
-        let new_comm = InternalCommunication {
-            intention: INSERT,
-            order: Some(new_order)
-        };
-        internal_order_channel_tx.send(new_comm).unwrap();
-        elevator.call_button_light(call_button.floor, call_button.call, true);
-    }  
+    let sensor = try_hardware(|| elevator.floor_sensor())?;
+    println!("Fikk kontakt med heisen igjen");
+    let mut actions = state_machine.handle(ElevatorEvent::DriverFailure(false), now);
+    if let Some(floor) = sensor {
+        actions.extend(state_machine.handle(ElevatorEvent::Floor(floor), now));
+    }
+    return Some(actions);
 }
 
-// Sends a hall call to the master
-fn handle_hall_call(comms_channel_tx:Sender<Communication>, call_button:CallButton, elevator:Elevator)-> () {
-    let new_order = Order {
-        floor_number: call_button.floor,
-        direction: call_button.call
-    };
+// Sends a hall call to the master. Its light is turned on once the master has confirmed it.
+fn handle_hall_call(comms_channel_tx:Sender<Communication>, new_order: Order)-> () {
     let new_comm = Communication {
         sender: u8::MAX,
-        sender_role: u8::MAX,
-        target: MASTER,
-        comm_type: ORDER_TRANSFER,
+        sender_role: None,
+        target: Target::Role(Role::Master),
+        comm_type: MessageKind::OrderTransfer,
         status: None,
-        order: Some(new_order)
+        order: Some(new_order),
+        hall_lights: None
     };
     comms_channel_tx.send(new_comm).unwrap();
-    elevator.call_button_light(call_button.floor, call_button.call, true);
 }
 
-// Elevator memory that keeps a destination list and a direction for message passing
-fn elevator_memory(internal_order_channel_rx: Receiver<InternalCommunication>, destination_list_tx: Sender<HashSet<Order>>, elevator_readout_tx: Sender<u8>) -> () {
-    let mut destination_list: HashSet<Order> = HashSet::new();
-    let mut direction: u8 = e::DIRN_DOWN;
-    loop {
-        cbc::select! {
-            recv(internal_order_channel_rx) -> a => {
-                let communication = a.unwrap();
-                match communication.intention {
-                    INSERT => { // add
-                        destination_list.insert(communication.order.unwrap());
-                    }
-                    DELETE => { // remove
-                        destination_list.remove(&communication.order.unwrap());
-                    }
-                    REQUEST_DESTINATION => {
-                        let destination_list_copy = destination_list.clone();
-                        destination_list_tx.send(destination_list_copy).unwrap();
-                    }
-                    REQUEST_DIRECTION => {
-                        elevator_readout_tx.send(direction).unwrap();
-                        // println!("Retning sendt: {:#?}", direction);
-                    }
-                    UPDATE_DIRECTION => {
-                        let order = communication.order.unwrap();
-                        direction = order.direction;
-                    }
-                    2_u8..=5_u8|9_u8..=u8::MAX => {
-                        println!("Wrong message to memory")
-                    }
-                }
-            }
-            // default(Duration::from_millis(100)) => {
-            //     //Chiller
-            // }
+// Carries out what the state machine asked for. This is the only place the elevator touches the hardware,
+// the journal and the network.
+// Hardware writes are skipped once the hardware has failed, as everything is written again after we reconnect.
+// Returns false if a write failed.
+fn apply_actions<H: ElevatorHardware>(
+    actions: Vec<ElevatorAction>,
+    elevator: &H,
+    state_machine: &ElevatorStateMachine,
+    cab_journal: &str,
+    comms_channel_tx: &Sender<Communication>,
+    hardware_failed: bool
+) -> bool {
+    let mut hardware_ok = !hardware_failed;
+    let mut write_to_hardware = |write: &dyn Fn()| {
+        if hardware_ok {
+            hardware_ok = try_hardware(write).is_some();
         }
-    }
-}
-
-// Controls the direction of the elevator through the elevator_controller channel
-fn handle_elevator_controller(elevator_controller_rx: Receiver<u8>, elevator: Elevator, internal_order_channel_tx: Sender<InternalCommunication>) -> () {
-    let mut direction: u8 = e::DIRN_DOWN;
-    loop {
-        cbc::select! {
-            recv(elevator_controller_rx) -> a => {
-                let direction_ordered = a.unwrap();
-                // println!("Mottat melding: {:#?}", direction_ordered);
-                match direction_ordered {
-                    e::DIRN_DOWN|e::DIRN_STOP|e::DIRN_UP => {
-                        direction = direction_ordered;
-                        elevator.motor_direction(direction);
-                        println!("Retning satt til {:#?}",direction_to_string(direction));
-                        let new_order = Order {
-                            floor_number: 0,
-                            direction: direction
-                        };
-                        let new_comm = InternalCommunication {
-                            intention: UPDATE_DIRECTION,
-                            order: Some(new_order)
-                        };
-                        internal_order_channel_tx.send(new_comm).unwrap();
-                    }
-                    DIRN_STOP_TEMP => {
-                        elevator.motor_direction(e::DIRN_STOP);
-                        println!("TEMPSTOP");
-                        sleep(Duration::from_millis(3000));
-                        elevator.motor_direction(direction);
-                    }
-                    2_u8|4_u8..=254_u8 => {
-                        println!("Feil ordre mottat i kontroller");
-                    }
-                }
-
+    };
+    for action in actions {
+        match action {
+            ElevatorAction::Motor(direction) => {
+                write_to_hardware(&|| elevator.motor_direction(direction));
+                println!("Retning satt til {:#?}", direction_to_string(direction));
             }
-        }
-    }
-}
-
-// Handles external communications from master; recieves new orders from master
-fn handle_message_from_master(message: Communication, internal_order_channel_tx: Sender<InternalCommunication>, comms_channel_tx: Sender<Communication>) -> () {
-    // println!("Recieved {:#?}", message);
-    match message.comm_type {
-        STATUS_MESSAGE => {
-            // Message is not for me
-        }
-        ORDER_TRANSFER => {
-            let new_order = message.order.unwrap();
-            let new_comm = InternalCommunication {
-                intention: INSERT,
-                order: Some(new_order)
-            };
-            // println!("ELEVATOR adding order {:#?}", message);
-            let internal_order_channel_tx = internal_order_channel_tx.clone();
-            internal_order_channel_tx.send(new_comm).unwrap();
-
-            let mut new_message = message;
-            new_message.target = MASTER;
-            new_message.comm_type = ORDER_ACK;
-            comms_channel_tx.send(new_message).unwrap();
-            sleep(Duration::from_millis(10));
-        }
-        ORDER_ACK => {
-            // Message is not for me
-        }
-        3_u8..=u8::MAX => {
-            println!("Feil i meldingssending")
-        }
-    }
-}
-
-fn send_elevator_startup(last_floor:u8,direction: u8,destination_list_copy: HashSet<Order>,elevator_controller_tx:Sender<u8>)->() {
-    if direction == e::DIRN_STOP {
-        if !destination_list_copy.is_empty() {
-            for destination in destination_list_copy{
-                if destination.floor_number > last_floor {
-                    elevator_controller_tx.send(e::DIRN_UP).unwrap();
-                    println!("Kjører");
-                    break;
-                }
-                if destination.floor_number < last_floor {
-                    elevator_controller_tx.send(e::DIRN_DOWN).unwrap();
-                    println!("Kjører");
-                    break;
+            ElevatorAction::DoorLight(on) => write_to_hardware(&|| elevator.door_light(on)),
+            ElevatorAction::StopLight(on) => write_to_hardware(&|| elevator.stop_button_light(on)),
+            ElevatorAction::CabLight(floor, on) => write_to_hardware(&|| elevator.call_button_light(floor, e::CAB, on)),
+            ElevatorAction::FloorIndicator(floor) => write_to_hardware(&|| elevator.floor_indicator(floor)),
+            ElevatorAction::SaveCabOrders => {
+                if let Err(e) = save_cab_orders(cab_journal, &state_machine.cab_orders()) {
+                    println!("Could not save cab orders: {}", e);
                 }
             }
+            ElevatorAction::AckOrder(order) => {
+                let ack = Communication {
+                    sender: u8::MAX,
+                    sender_role: None,
+                    target: Target::Role(Role::Master),
+                    comm_type: MessageKind::OrderAck,
+                    status: None,
+                    order: Some(order),
+                    hall_lights: None
+                };
+                comms_channel_tx.send(ack).unwrap();
+            }
+            // Let every unit know the call is cleared, so the master takes it off its order list
+            // and the other panels turn off its light
+            ElevatorAction::ClearHallCall(order) => {
+                let cleared_message = Communication {
+                    sender: u8::MAX,
+                    sender_role: None,
+                    target: Target::All,
+                    comm_type: MessageKind::HallCallCleared,
+                    status: None,
+                    order: Some(order),
+                    hall_lights: None
+                };
+                comms_channel_tx.send(cleared_message).unwrap();
+            }
         }
-    };
+    }
+    return hardware_failed || hardware_ok;
 }
 
-// Create and send status to master
-fn send_status_update(last_floor:u8,direction: u8,destination_list: HashSet<Order>,comms_channel_tx:Sender<Communication>)->() {
-    // println!("{:#?}", destination_list_r);
-    
-    let current_status = Status {
-        last_floor: last_floor,
-        direction: direction,
-        errors: false,
-        obstructions: false,
-        target_floor: target_floor_function(direction, destination_list, last_floor)
-    };
-    
+// Send our status to the master
+fn send_status_update(status: Status, comms_channel_tx: &Sender<Communication>) -> () {
     let new_message = Communication {
         sender: u8::MAX,
-        sender_role: u8::MAX,
-        target: TARGET_ALL,
-        comm_type: STATUS_MESSAGE,
-        status: Some(current_status),
-        order: None
+        sender_role: None,
+        target: Target::All,
+        comm_type: MessageKind::Status,
+        status: Some(status),
+        order: None,
+        hall_lights: None
     };
     comms_channel_tx.send(new_message).unwrap();
 }
@@ -325,16 +205,16 @@ fn send_status_update(last_floor:u8,direction: u8,destination_list: HashSet<Orde
 fn readout_status(last_floor:u8,direction: u8,destination_list: HashSet<Order>,last_last_floor: &mut u8,last_destination_list: &mut HashSet<Order>)->() {
     let mut destinations_up: HashSet<u8> = HashSet::new();
     let mut destinations_down: HashSet<u8> = HashSet::new();
+    let mut destinations_cab: HashSet<u8> = HashSet::new();
 
     for element in &destination_list {
-        if element.direction == e::HALL_UP {
-            destinations_up.insert(element.floor_number);
-        }
-        else if element.direction == e::HALL_DOWN {
-            destinations_down.insert(element.floor_number);
-        }
+        match element.call {
+            CallKind::HallUp => destinations_up.insert(element.floor_number),
+            CallKind::HallDown => destinations_down.insert(element.floor_number),
+            CallKind::Cab => destinations_cab.insert(element.floor_number),
+        };
     }
-    
+
     if destination_list != *last_destination_list || last_floor != *last_last_floor {
         *last_destination_list = destination_list.clone();
         *last_last_floor = last_floor.clone();
@@ -344,6 +224,7 @@ fn readout_status(last_floor:u8,direction: u8,destination_list: HashSet<Order>,l
         vec!["Retning".cell(), direction_to_string(direction).cell().justify(Justify::Right)],
         vec!["Destinasjoner opp".cell(), format!("{:#?}", destinations_up.clone()).cell().justify(Justify::Right)],
         vec!["Destinasjoner ned".cell(), format!("{:#?}", destinations_down.clone()).cell().justify(Justify::Right)],
+        vec!["Destinasjoner cab".cell(), format!("{:#?}", destinations_cab.clone()).cell().justify(Justify::Right)],
         ]
         .table()
         .title(vec![
@@ -356,139 +237,228 @@ fn readout_status(last_floor:u8,direction: u8,destination_list: HashSet<Order>,l
     }
 }
 
-// Elevator function. Runs forever (or till it panics)
-pub fn run_elevator(id:u8,elev_num_floors: u8, elevator: Elevator, poll_period: Duration, comms_channel_tx: Sender<Communication>, comms_channel_rx: Receiver<Communication>) -> () {
+// What an elevator needs to know about itself
+#[derive(Clone, Debug)]
+pub struct ElevatorSettings {
+    pub id: u8,
+    pub num_floors: u8,
+    pub poll_period: Duration,
+    pub door_open_time: Duration,
+    pub motor_timeout: Duration,
+    pub cab_journal: String, // File the cab orders are saved to
+    pub clock: Arc<dyn Clock>,
+}
+
+// The channels an elevator talks to the rest of the unit on
+pub struct ElevatorChannels {
+    pub comms_channel_tx: Sender<Communication>, // Out to the network
+    pub comms_channel_rx: Receiver<Communication>, // In from the network
+    pub pair_state_tx: Option<Sender<PairState>>, // To our backup in process pair mode
+}
+
+// Elevator function. Runs until stopped is set, and so do the pollers it starts
+// Everything the elevator does is decided by the state machine in this one thread, from the events
+// of the pollers, the network and the clock.
+// restored_state is the state of the process we took over from in process pair mode
+pub fn run_elevator<H: ElevatorHardware>(
+    elevator: H,
+    settings: ElevatorSettings,
+    channels: ElevatorChannels,
+    restored_state: Option<PairState>,
+    stopped: Arc<atomic::AtomicBool>
+) -> () {
+    let ElevatorSettings { id, num_floors: elev_num_floors, poll_period, door_open_time, motor_timeout, cab_journal, clock } = settings;
+    let ElevatorChannels { comms_channel_tx, comms_channel_rx, pair_state_tx } = channels;
 
     // Initialize call buttons
-    let (call_button_tx, call_button_rx) = cbc::unbounded::<elevio::poll::CallButton>();
+    let (call_button_tx, call_button_rx) = cbc::unbounded::<CallButton>();
     {
         let elevator = elevator.clone();
-        spawn(move || elevio::poll::call_buttons(elevator, call_button_tx, poll_period));
+        let clock = clock.clone();
+        let stopped = stopped.clone();
+        spawn(move || poll_call_buttons(elevator, call_button_tx, poll_period, clock, stopped));
     }
     // Initialize floor sensor
-    let (floor_sensor_tx, floor_sensor_rx) = cbc::unbounded::<u8>(); 
+    let (floor_sensor_tx, floor_sensor_rx) = cbc::unbounded::<u8>();
+    let (driver_failure_tx, driver_failure_rx) = cbc::unbounded::<()>();
     {
         let elevator = elevator.clone();
-        spawn(move || elevio::poll::floor_sensor(elevator, floor_sensor_tx, poll_period));
+        let clock = clock.clone();
+        let stopped = stopped.clone();
+        spawn(move || poll_floor_sensor(elevator, floor_sensor_tx, driver_failure_tx, poll_period, clock, stopped));
     }
     // Initialize stop button
-    let (stop_button_tx, _stop_button_rx) = cbc::unbounded::<bool>(); 
+    let (stop_button_tx, stop_button_rx) = cbc::unbounded::<bool>();
     {
         let elevator = elevator.clone();
-        spawn(move || elevio::poll::stop_button(elevator, stop_button_tx, poll_period));
+        let clock = clock.clone();
+        let stopped = stopped.clone();
+        spawn(move || poll_stop_button(elevator, stop_button_tx, poll_period, clock, stopped));
     }
     // Initialize obstruction switch
-    let (obstruction_tx, _obstruction_rx) = cbc::unbounded::<bool>(); 
+    let (obstruction_tx, obstruction_rx) = cbc::unbounded::<bool>();
     {
         let elevator = elevator.clone();
-        spawn(move || elevio::poll::obstruction(elevator, obstruction_tx, poll_period));
+        let clock = clock.clone();
+        let stopped = stopped.clone();
+        spawn(move || poll_obstruction(elevator, obstruction_tx, poll_period, clock, stopped));
     }
 
-    // Set up direction variable
-    //let mut dirn = e::DIRN_DOWN; 
-    // Send the elevator down upon startup
-    elevator.motor_direction(e::DIRN_DOWN);
-    println!("På vei ned");
-    
-    // Set up variable to remember what floor we were last at
-    let mut last_floor: u8 = elev_num_floors+1;
-
     // Setting up last_last variables for the purposes of readout function
     let mut last_destination_list: HashSet<Order> = HashSet::new();
     let mut last_last_floor: u8 = 0;
 
-    let (internal_order_channel_tx, internal_order_channel_rx) = cbc::bounded(1);
-    let (destination_list_tx, destination_list_rx) = cbc::bounded(1);
-
-    let (elevator_controller_tx, elevator_controller_rx) = cbc::bounded(1);
-    let (elevator_readout_tx, elevator_readout_rx) = cbc::bounded::<u8>(1);
-
-    {
-    let elevator_readout_tx = elevator_readout_tx.clone();
-    spawn(move || elevator_memory(internal_order_channel_rx, destination_list_tx, elevator_readout_tx));
+    // Start the state machine with the cab orders from before a crash or restart
+    let mut state_machine = ElevatorStateMachine::new(elev_num_floors, door_open_time, motor_timeout, clock.now());
+    let cab_orders = load_cab_orders(&cab_journal);
+    for order in &cab_orders {
+        println!("Restored cab order to floor {}", order.floor_number);
     }
+    if let Some(state) = &restored_state {
+        println!("Restored {} destinations from the primary", state.destination_list.len());
+    }
+    let mut actions = state_machine.start(cab_orders, restored_state, clock.now());
 
-    {
-    let elevator = elevator.clone();
-    let elevator_controller_rx = elevator_controller_rx.clone();
-    let internal_order_channel_tx = internal_order_channel_tx.clone();
-    spawn(move || handle_elevator_controller(elevator_controller_rx, elevator, internal_order_channel_tx));
+    // Hall lights are driven by the master, so they all start off. hall_lights is what the buttons show,
+    // and wanted_hall_lights what the master last asked for.
+    let mut hardware_failed = false;
+    let mut hall_lights = Destinations { up: u16::MAX, down: u16::MAX, cab: 0 };
+    let mut wanted_hall_lights = Destinations::default();
+    if !update_hall_lights(&elevator, &mut hall_lights, wanted_hall_lights, elev_num_floors) {
+        actions.extend(driver_failed(&mut state_machine, &mut hardware_failed, clock.now()));
     }
+    // A write that fails is a driver failure, the same as a read that fails
+    while !apply_actions(actions, &elevator, &state_machine, &cab_journal, &comms_channel_tx, hardware_failed) {
+        actions = driver_failed(&mut state_machine, &mut hardware_failed, clock.now());
+    }
+
+    // The state machine checks its timers on every tick
+    let ticker = clock.tick(poll_period);
+    // Statuses go out on a fixed tick, so they are sent even while the hall lights keep coming in
+    let status_ticker = clock.tick(Duration::from_millis(1000));
 
     // The main running loop of the elevator
-    loop {
-        // Crossbeam channel runs the main functions of the master
-        // It constantly checks whether it has received a message and runs a standard function if it has waited too long
-        cbc::select! {
-            // Get info from call button and add it to the destination list if it is a cab call
-            recv(call_button_rx) -> a => { 
-                let call_button = a.unwrap();
-                if call_button.call == e::CAB {
-                    let elevator = elevator.clone();
-                    let internal_order_channel_tx = internal_order_channel_tx.clone();
-                    spawn(move||handle_cab_order(call_button, last_floor, elevator, internal_order_channel_tx));
-                } else {
-                    let elevator = elevator.clone();
-                    let comms_channel_tx = comms_channel_tx.clone();
-                    handle_hall_call(comms_channel_tx, call_button, elevator); // Sends new hall call to master
+    while !stopped.load(atomic::Ordering::Relaxed) {
+        let before = Logged::of(&state_machine);
+        // Crossbeam channel runs the main functions of the elevator
+        // Every message becomes an event for the state machine, and the actions it gives back are carried out below
+        let mut actions = cbc::select! {
+            // Cab calls go to the state machine, hall calls to the master.
+            // The pollers return once the unit is stopped, so their channels closing means we are done as well.
+            recv(call_button_rx) -> a => {
+                let Ok(call_button) = a else {
+                    return;
+                };
+                match CallKind::from_button(call_button.call) {
+                    Some(CallKind::Cab) => state_machine.handle(ElevatorEvent::CabCall(call_button.floor), clock.now()),
+                    Some(call) => {
+                        handle_hall_call(comms_channel_tx.clone(), Order { floor_number: call_button.floor, call });
+                        Vec::new()
+                    }
+                    None => Vec::new(),
                 }
             }
-            // Get floor status and save last floor for later use
             recv(floor_sensor_rx) -> a => {
-                let floor = a.unwrap();
-                last_floor = floor;
-                println!("{:#?}", floor);
-                {
-                let elevator = elevator.clone();
-                let internal_order_channel_tx = internal_order_channel_tx.clone();
-                let elevator_controller_tx = elevator_controller_tx.clone();
-                let elevator_readout_rx = elevator_readout_rx.clone();
-                let destination_list_rx = destination_list_rx.clone();
-                spawn(move || floor_recieved(floor, last_floor, elevator, elev_num_floors, internal_order_channel_tx, elevator_controller_tx, elevator_readout_rx, destination_list_rx));
-                }
+                let Ok(floor) = a else {
+                    return;
+                };
+                state_machine.handle(ElevatorEvent::Floor(floor), clock.now())
             }
-            // Get info from comms_channel and process according to status if it is meant for us
-            recv(comms_channel_rx) -> a => {
-                let message = a.unwrap();
-                if message.target == id {
-                    println!("Elevator Recieved {:#?}", message);
-                    let internal_order_channel_tx = internal_order_channel_tx.clone();
-                    let comms_channel_tx = comms_channel_tx.clone();
-                    spawn (move || handle_message_from_master(message, internal_order_channel_tx, comms_channel_tx));
+            // The poller can be a bit behind, so the floor sensor is read again to know whether the door may open
+            recv(stop_button_rx) -> a => {
+                let Ok(pressed) = a else {
+                    return;
+                };
+                let sensor = try_hardware(|| elevator.floor_sensor());
+                let event = ElevatorEvent::StopButton { pressed, at_floor: sensor.flatten().is_some() };
+                let mut actions = state_machine.handle(event, clock.now());
+                if sensor.is_none() {
+                    actions.extend(driver_failed(&mut state_machine, &mut hardware_failed, clock.now()));
                 }
+                actions
             }
-            // This function polls continuously
-            default(Duration::from_millis(1000)) => {
-                let new_comm2 = InternalCommunication {
-                    intention: REQUEST_DIRECTION,
-                    order: None
+            recv(obstruction_rx) -> a => {
+                let Ok(obstruction) = a else {
+                    return;
                 };
-                internal_order_channel_tx.send(new_comm2).unwrap();
-                let direction = elevator_readout_rx.recv().unwrap();
-                
-                let new_comm = InternalCommunication {
-                    intention: REQUEST_DESTINATION,
-                    order: None
+                println!("Obstruksjon: {}", obstruction);
+                state_machine.handle(ElevatorEvent::Obstruction(obstruction), clock.now())
+            }
+            recv(driver_failure_rx) -> a => {
+                if a.is_err() {
+                    return;
+                }
+                driver_failed(&mut state_machine, &mut hardware_failed, clock.now())
+            }
+            // Get info from comms_channel and process according to status if it is meant for us
+            recv(comms_channel_rx) -> a => {
+                // The network threads are gone once the unit is stopped
+                let Ok(message) = a else {
+                    return;
                 };
-                internal_order_channel_tx.send(new_comm).unwrap();
-                let a = destination_list_rx.recv();
-                let destination_list = a.unwrap();
-        
-                {
-                let destination_list_copy = destination_list.clone();
-                let elevator_controller_tx = elevator_controller_tx.clone();
-                send_elevator_startup(last_floor,direction,destination_list_copy,elevator_controller_tx);
+                match message.comm_type {
+                    MessageKind::HallLights => {
+                        wanted_hall_lights = message.hall_lights.unwrap();
+                        if show_hall_lights(&elevator, &mut hall_lights, wanted_hall_lights, elev_num_floors, hardware_failed) {
+                            Vec::new()
+                        } else {
+                            driver_failed(&mut state_machine, &mut hardware_failed, clock.now())
+                        }
+                    }
+                    MessageKind::HallCallCleared => { // Served calls go dark right away, without waiting for the master
+                        let mut lit = wanted_hall_lights.to_orders();
+                        lit.remove(&message.order.unwrap());
+                        wanted_hall_lights = Destinations::from_orders(&lit);
+                        if show_hall_lights(&elevator, &mut hall_lights, wanted_hall_lights, elev_num_floors, hardware_failed) {
+                            Vec::new()
+                        } else {
+                            driver_failed(&mut state_machine, &mut hardware_failed, clock.now())
+                        }
+                    }
+                    MessageKind::OrderTransfer if message.target == Target::Unit(id) => {
+                        println!("Elevator Recieved {:#?}", message);
+                        let actions = state_machine.handle(ElevatorEvent::OrderAssigned(message.order.unwrap()), clock.now());
+                        if !actions.iter().any(|action| matches!(action, ElevatorAction::AckOrder(_))) {
+                            println!("Tar ikke imot ordre nå");
+                        }
+                        actions
+                    }
+                    _ => Vec::new()
                 }
-                {
-                let destination_list_copy = destination_list.clone();
-                let comms_channel_tx = comms_channel_tx.clone();
-                send_status_update(last_floor,direction,destination_list_copy,comms_channel_tx);
+            }
+            recv(ticker) -> _ => {
+                state_machine.handle(ElevatorEvent::Tick, clock.now())
+            }
+            recv(status_ticker) -> _ => {
+                // Try the hardware again once a second while it is gone. Every hall light is written again as well.
+                let mut actions = Vec::new();
+                if hardware_failed {
+                    if let Some(reconnected) = reconnect(&elevator, &mut state_machine, clock.now()) {
+                        hardware_failed = false;
+                        actions = reconnected;
+                        hall_lights = Destinations { up: !wanted_hall_lights.up, down: !wanted_hall_lights.down, cab: 0 };
+                        if !update_hall_lights(&elevator, &mut hall_lights, wanted_hall_lights, elev_num_floors) {
+                            actions.extend(driver_failed(&mut state_machine, &mut hardware_failed, clock.now()));
+                        }
+                    }
                 }
-                {
-                let destination_list_copy = destination_list.clone();
-                readout_status(last_floor, direction, destination_list_copy,&mut last_last_floor,&mut last_destination_list);
+                let status = state_machine.status();
+                send_status_update(status, &comms_channel_tx);
+                if let Some(pair_state_tx) = &pair_state_tx {
+                    let pair_state = PairState {
+                        last_floor: status.last_floor,
+                        direction: status.direction,
+                        destination_list: state_machine.destinations.clone()
+                    };
+                    pair_state_tx.send(pair_state).unwrap();
                 }
+                readout_status(status.last_floor, status.direction, state_machine.destinations.clone(), &mut last_last_floor, &mut last_destination_list);
+                actions
             }
+        };
+        while !apply_actions(actions, &elevator, &state_machine, &cab_journal, &comms_channel_tx, hardware_failed) {
+            actions = driver_failed(&mut state_machine, &mut hardware_failed, clock.now());
         }
+        log_changes(before, Logged::of(&state_machine));
     }
-}
\ No newline at end of file
+}
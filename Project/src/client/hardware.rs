@@ -0,0 +1,194 @@
+use crate::prelude::*;
+use crate::clock::Clock;
+
+// Everything the elevator logic needs from the hardware. Implemented by the real driver,
+// and by the simulated shaft so the elevator can run without the hardware server.
+// Any of the calls may panic if the hardware is lost, so they are made through try_hardware.
+pub trait ElevatorHardware: Clone + Send + Sync + 'static {
+    fn num_floors(&self) -> u8;
+    fn motor_direction(&self, dirn: u8);
+    fn call_button_light(&self, floor: u8, call: u8, on: bool);
+    fn floor_indicator(&self, floor: u8);
+    fn door_light(&self, on: bool);
+    fn stop_button_light(&self, on: bool);
+    fn call_button(&self, floor: u8, call: u8) -> bool;
+    fn floor_sensor(&self) -> Option<u8>;
+    fn stop_button(&self) -> bool;
+    fn obstruction(&self) -> bool;
+    // Connects to the hardware again after a failure. Returns whether it worked.
+    fn reconnect(&self) -> bool;
+}
+
+// The real hardware, through driver_rust. The driver panics on a failed read or write while it holds the lock
+// on its socket, which poisons the lock for good, so after a failure we connect again instead of using the old handle.
+// Clones share the connection, so they all use the new one.
+#[derive(Clone, Debug)]
+pub struct Driver {
+    addr: String,
+    num_floors: u8,
+    elevator: Arc<Mutex<Elevator>>,
+}
+
+impl Driver {
+    pub fn init(addr: &str, num_floors: u8) -> std::io::Result<Self> {
+        let elevator = Elevator::init(addr, num_floors)?;
+        return Ok(Driver {
+            addr: addr.to_string(),
+            num_floors,
+            elevator: Arc::new(Mutex::new(elevator)),
+        });
+    }
+    // The current connection. Our own lock is never held while the driver is called, so it can't be poisoned.
+    fn elevator(&self) -> Elevator {
+        self.elevator.lock().unwrap().clone()
+    }
+}
+
+impl ElevatorHardware for Driver {
+    fn num_floors(&self) -> u8 {
+        self.num_floors
+    }
+    fn motor_direction(&self, dirn: u8) {
+        self.elevator().motor_direction(dirn)
+    }
+    fn call_button_light(&self, floor: u8, call: u8, on: bool) {
+        self.elevator().call_button_light(floor, call, on)
+    }
+    fn floor_indicator(&self, floor: u8) {
+        self.elevator().floor_indicator(floor)
+    }
+    fn door_light(&self, on: bool) {
+        self.elevator().door_light(on)
+    }
+    fn stop_button_light(&self, on: bool) {
+        self.elevator().stop_button_light(on)
+    }
+    fn call_button(&self, floor: u8, call: u8) -> bool {
+        self.elevator().call_button(floor, call)
+    }
+    fn floor_sensor(&self) -> Option<u8> {
+        self.elevator().floor_sensor()
+    }
+    fn stop_button(&self) -> bool {
+        self.elevator().stop_button()
+    }
+    fn obstruction(&self) -> bool {
+        self.elevator().obstruction()
+    }
+    fn reconnect(&self) -> bool {
+        match Elevator::init(&self.addr, self.num_floors) {
+            Ok(elevator) => {
+                *self.elevator.lock().unwrap() = elevator;
+                return true;
+            }
+            Err(e) => {
+                println!("Could not connect to the elevator at {}: {}", self.addr, e);
+                return false;
+            }
+        }
+    }
+}
+
+// How often the pollers try the hardware again after it has failed
+const DRIVER_RETRY_PERIOD: Duration = Duration::from_millis(1000);
+
+// Reads from or writes to the hardware. The driver panics if it loses the connection to the hardware server,
+// so this returns None instead of taking the thread down with it.
+pub fn try_hardware<T, F: FnOnce() -> T>(call: F) -> Option<T> {
+    return std::panic::catch_unwind(std::panic::AssertUnwindSafe(call)).ok();
+}
+
+// The polling functions below work like the ones in driver_rust::elevio::poll, but for any hardware.
+// They wait a while and try again if the hardware can't be read, and return once stopped is set
+// or the elevator has stopped listening.
+
+// Sends a call button every time it is pressed
+pub fn poll_call_buttons<H: ElevatorHardware>(elevator: H, call_button_tx: Sender<CallButton>, period: Duration, clock: Arc<dyn Clock>, stopped: Arc<atomic::AtomicBool>) {
+    let num_floors = elevator.num_floors() as usize;
+    let mut prev = vec![[false; 3]; num_floors];
+    while !stopped.load(atomic::Ordering::Relaxed) {
+        let mut failed = false;
+        for (floor, row) in prev.iter_mut().enumerate() {
+            for (call, was_pressed) in row.iter_mut().enumerate() {
+                let Some(pressed) = try_hardware(|| elevator.call_button(floor as u8, call as u8)) else {
+                    failed = true;
+                    break;
+                };
+                if pressed && *was_pressed != pressed && call_button_tx.send(CallButton { floor: floor as u8, call: call as u8 }).is_err() {
+                    return;
+                }
+                *was_pressed = pressed;
+            }
+        }
+        clock.sleep(if failed { DRIVER_RETRY_PERIOD } else { period });
+    }
+}
+
+// Sends the floor every time the elevator arrives at a new one.
+// driver_failure_tx is sent to when the hardware can't be read. The elevator connects again,
+// and the floor is sent again once it can be read, even if it is the same.
+pub fn poll_floor_sensor<H: ElevatorHardware>(elevator: H, floor_sensor_tx: Sender<u8>, driver_failure_tx: Sender<()>, period: Duration, clock: Arc<dyn Clock>, stopped: Arc<atomic::AtomicBool>) {
+    let mut prev = u8::MAX;
+    let mut failed = false;
+    while !stopped.load(atomic::Ordering::Relaxed) {
+        match try_hardware(|| elevator.floor_sensor()) {
+            Some(sensor) => {
+                failed = false;
+                if let Some(floor) = sensor {
+                    if floor != prev {
+                        if floor_sensor_tx.send(floor).is_err() {
+                            return;
+                        }
+                        prev = floor;
+                    }
+                }
+            }
+            None => {
+                if !failed {
+                    failed = true;
+                    prev = u8::MAX;
+                    if driver_failure_tx.send(()).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        clock.sleep(if failed { DRIVER_RETRY_PERIOD } else { period });
+    }
+}
+
+// Sends the state of the stop button every time it changes
+pub fn poll_stop_button<H: ElevatorHardware>(elevator: H, stop_button_tx: Sender<bool>, period: Duration, clock: Arc<dyn Clock>, stopped: Arc<atomic::AtomicBool>) {
+    let mut prev = false;
+    while !stopped.load(atomic::Ordering::Relaxed) {
+        let Some(stop) = try_hardware(|| elevator.stop_button()) else {
+            clock.sleep(DRIVER_RETRY_PERIOD);
+            continue;
+        };
+        if stop != prev {
+            if stop_button_tx.send(stop).is_err() {
+                return;
+            }
+            prev = stop;
+        }
+        clock.sleep(period);
+    }
+}
+
+// Sends the state of the obstruction switch every time it changes
+pub fn poll_obstruction<H: ElevatorHardware>(elevator: H, obstruction_tx: Sender<bool>, period: Duration, clock: Arc<dyn Clock>, stopped: Arc<atomic::AtomicBool>) {
+    let mut prev = false;
+    while !stopped.load(atomic::Ordering::Relaxed) {
+        let Some(obstructed) = try_hardware(|| elevator.obstruction()) else {
+            clock.sleep(DRIVER_RETRY_PERIOD);
+            continue;
+        };
+        if obstructed != prev {
+            if obstruction_tx.send(obstructed).is_err() {
+                return;
+            }
+            prev = obstructed;
+        }
+        clock.sleep(period);
+    }
+}
@@ -0,0 +1,43 @@
+use crate::prelude::*;
+use std::fs;
+use std::io::Write;
+
+// An entry in a journal from before orders had a CallKind, like {"floor_number": 2, "direction": 2}.
+// Only cab orders were ever saved, so the rest of the entry is not needed.
+#[derive(Deserialize)]
+struct OldCabOrder {
+    floor_number: u8,
+}
+
+// Reads the cab orders saved before a crash or restart. A missing or broken file means no orders.
+// Journals in the old format are read as well, and every entry in them is a cab order.
+pub fn load_cab_orders(path: &str) -> HashSet<Order> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return HashSet::new(),
+    };
+    match serde_json::from_str::<HashSet<Order>>(&text) {
+        Ok(cab_orders) => cab_orders,
+        Err(e) => match serde_json::from_str::<Vec<OldCabOrder>>(&text) {
+            Ok(old_orders) => old_orders.iter()
+                .map(|old| Order { floor_number: old.floor_number, call: CallKind::Cab })
+                .collect(),
+            Err(_) => {
+                println!("Could not read cab orders from {}: {}", path, e);
+                HashSet::new()
+            }
+        }
+    }
+}
+
+// Saves the cab orders by writing a temporary file and renaming it,
+// so a crash in the middle of writing never leaves a half written file behind.
+pub fn save_cab_orders(path: &str, cab_orders: &HashSet<Order>) -> anyhow::Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    let json = serde_json::to_string(cab_orders)?;
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(json.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    return Ok(());
+}
@@ -1,179 +1,528 @@
 use crate::prelude::*;
 use crate::network::server::*;
+use crate::client::assigner::*;
+use std::collections::{BTreeMap, BTreeSet};
 
-// Finds the relative distance to an order based on the current target floor.
-fn cost_of_order(order: Order, status: Status) -> u8 {
-    println!("Finding cost of {:#?}", order);
-    println!("with {:#?}", status);
+// How long a unit has to acknowledge an order once the network has delivered it, before it is given to another one
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+// How long a unit can hold an acknowledged order before it is given to another one
+const SERVE_TIMEOUT: Duration = Duration::from_secs(60);
 
-    let last_floor = i32::from(status.last_floor);
-    let order_floor = i32::from(order.floor_number);
+// Where a hall order is in its life on the master. Served orders are taken out of the list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OrderState {
+    New, // In the order list, not given to any unit
+    Pending { unit: u8, delivered_at: Option<Instant> }, // Sent to a unit, waiting for the network to deliver it and the unit to acknowledge
+    Acknowledged { unit: u8, since: Instant }, // The unit has the order among its destinations
+}
 
-    if status.target_floor.is_none() {
-        return i32::abs(last_floor - order_floor) as u8;
+impl OrderState {
+    fn unit(&self) -> Option<u8> {
+        match self {
+            OrderState::New => None,
+            OrderState::Pending { unit, .. } | OrderState::Acknowledged { unit, .. } => Some(*unit),
+        }
     }
+}
+
+// What the master asks of the order memory. Requests with an answer carry their own reply channel,
+// so every answer goes back to the one who asked.
+#[derive(Debug)]
+enum MemoryRequest {
+    Insert(Order),
+    Delete(Order),
+    Assigned(Order), // An elevator has acknowledged the order
+    ConfirmBackupInsert(Order),
+    ConfirmBackupDelete(Order),
+    ResyncBackup, // There is a new backup
+    BackupGone, // There is no backup, so nothing is sent to it until there is a new one
+    OrderList(Sender<HashSet<Order>>), // The orders not given to any elevator yet
+    BackedUp(Sender<HashSet<Order>>), // The orders the backup has acknowledged
+}
+
+// Asks the order memory for a list of orders and waits for the answer on a channel of our own
+fn ask_order_memory(order_memory_tx: &Sender<MemoryRequest>, request: fn(Sender<HashSet<Order>>) -> MemoryRequest) -> HashSet<Order> {
+    let (reply_tx, reply_rx) = cbc::bounded(1);
+    order_memory_tx.send(request(reply_tx)).unwrap();
+    return reply_rx.recv().unwrap();
+}
 
-    let target_floor = i32::from(status.target_floor.unwrap()); //
-    let cost:i32;
-    
-    // Finds whether the order floor is between last_floor and target_floor, 
-    // also checks if the order direction is the same as the elevator direction.
-    if (order.direction==DIRN_UP && (target_floor>order_floor && order_floor>last_floor))
-    || (order.direction==DIRN_DOWN && (target_floor<order_floor && order_floor<last_floor)) {
-        // If the order floor is on our path, the cost is the distance between last_floor and order_floor
-        cost = i32::abs(last_floor - order_floor);
+// Orders are sent once. The network layer resends them until they are delivered, and reports whether they were.
+fn send_order(network_unit: &NetworkUnit, delivery_tx: &Sender<DeliveryReport>, order: Order, unit_id: u8) -> () {
+    let new_message = Communication {
+        sender: u8::MAX,  // System-generated message
+        sender_role: None,
+        target: Target::Unit(unit_id),  // Target the unit by its ID
+        comm_type: MessageKind::OrderTransfer,
+        status: None,
+        order: Some(order),
+        hall_lights: None
+    };
+
+    println!("Sending order to unit {}: {:?}", unit_id, new_message.order);
+    network_unit.send_reliable(new_message, delivery_tx);
+}
+
+// A unit that got its order has ACK_TIMEOUT to acknowledge it, and an order that could not be delivered
+// goes to another unit. Reports about orders that have moved on since they were sent are ignored.
+fn order_delivered(order_memory_tx: &Sender<MemoryRequest>, orders: &mut BTreeMap<Order, OrderState>, message: Communication, delivered: bool, now: Instant) -> () {
+    let (Some(order), Target::Unit(unit)) = (message.order, message.target) else {
+        return;
+    };
+    if orders.get(&order) != Some(&OrderState::Pending { unit, delivered_at: None }) {
+        return;
+    }
+    if delivered {
+        orders.insert(order, OrderState::Pending { unit, delivered_at: Some(now) });
     } else {
-        // If the order floor is not on our path, the cost is the distance from last_floor to target_floor to order_floor
-        cost = i32::abs(last_floor - target_floor) + i32::abs(target_floor - order_floor);
+        println!("Order {:?} could not be delivered to unit {}", order, unit);
+        release_order(order_memory_tx, orders, order);
     }
-    return cost as u8;
 }
 
-// Sends orders to the elevator
-fn order_up(
-    comms_channel_tx: Sender<Communication>,
-    order_list: HashSet<Order>,
-    state_list: HashSet<State>,
-) -> () {
-    let status_list: Vec<Status> = state_list.iter().map(|state| state.status).collect();
-
-    let mut cost_of_orders = Vec::new();
-    for order in &order_list {
-        // Calculate costs for this order against all statuses
-        cost_of_orders.clear();
-        for status in &status_list {
-            cost_of_orders.push(cost_of_order(*order, *status));
+// Puts an order back in the order list, so order_up gives it to another unit
+fn release_order(order_memory_tx: &Sender<MemoryRequest>, orders: &mut BTreeMap<Order, OrderState>, order: Order) -> () {
+    if let Some(state) = orders.get_mut(&order) {
+        if let OrderState::Acknowledged { .. } = state {
+            order_memory_tx.send(MemoryRequest::Insert(order)).unwrap();
         }
+        *state = OrderState::New;
+    }
+}
 
-        // Find the unit with minimum cost
-        let min_cost = cost_of_orders.iter().min().unwrap();
-        let (best_unit_index, _) = cost_of_orders.iter()
-            .enumerate()
-            .find(|(_, &cost)| cost == *min_cost)
-            .unwrap();
+// Puts the orders held by a unit back in the order list
+fn release_orders(order_memory_tx: &Sender<MemoryRequest>, orders: &mut BTreeMap<Order, OrderState>, id: u8) -> () {
+    let held: Vec<Order> = orders.iter()
+        .filter(|(_, state)| state.unit() == Some(id))
+        .map(|(order, _)| *order)
+        .collect();
+    for order in held {
+        println!("Releasing order {:?} from unit {}", order, id);
+        release_order(order_memory_tx, orders, order);
+    }
+}
 
-        // Get the corresponding State to find the unit ID
-        let best_unit_state = state_list.iter()
-            .nth(best_unit_index)
-            .unwrap();
+// Moves the orders along their lifecycle. New orders are given to a unit and sent once. Orders that are not
+// acknowledged within ACK_TIMEOUT of being delivered are given to another unit.
+// Orders are only taken from a unit when it can't take orders anymore, or has not served them within SERVE_TIMEOUT.
+fn order_up(
+    network_unit: &NetworkUnit,
+    delivery_tx: &Sender<DeliveryReport>,
+    order_memory_tx: &Sender<MemoryRequest>,
+    orders: &mut BTreeMap<Order, OrderState>,
+    state_list: HashSet<State>,
+    assigner: &mut dyn Assigner
+) -> () {
+    // A unit's destinations are the ones in its last status, and the orders given to it since
+    let mut units: Units = BTreeMap::new();
+    for state in &state_list {
+        units.insert(state.id, (state.status, state.status.destinations.to_orders()));
+    }
 
-        let new_message = Communication {
-            sender: u8::MAX,  // System-generated message
-            sender_role: u8::MAX,
-            target: best_unit_state.id,  // Target the unit by its ID
-            comm_type: ORDER_TRANSFER,
-            status: None,
-            order: Some(*order)
-        };
+    let now = network_unit.clock.now();
+    let held: Vec<(Order, OrderState)> = orders.iter().map(|(order, state)| (*order, *state)).collect();
+    for (order, state) in held {
+        match state {
+            OrderState::New => {}
+            OrderState::Pending { unit, .. } if !units.contains_key(&unit) => {
+                println!("Unit {} can't take order {:?}", unit, order);
+                release_order(order_memory_tx, orders, order);
+            }
+            OrderState::Pending { unit, delivered_at: Some(delivered_at) } if now.duration_since(delivered_at) >= ACK_TIMEOUT => {
+                println!("Unit {} never acknowledged order {:?}", unit, order);
+                release_order(order_memory_tx, orders, order);
+            }
+            OrderState::Acknowledged { unit, since } if now.duration_since(since) >= SERVE_TIMEOUT => {
+                println!("Unit {} has not served order {:?} in time", unit, order);
+                release_order(order_memory_tx, orders, order);
+            }
+            OrderState::Pending { .. } | OrderState::Acknowledged { .. } => {}
+        }
+    }
 
-        println!("Sending order to unit {}: {:?}", best_unit_state.id, new_message.order);
-        comms_channel_tx.send(new_message).unwrap();
+    // The orders given out earlier count as destinations when the new ones are placed
+    for (order, state) in orders.iter() {
+        if let Some(unit) = state.unit() {
+            if let Some((_, destinations)) = units.get_mut(&unit) {
+                destinations.insert(*order);
+            }
+        }
+    }
+    let new_orders: BTreeSet<Order> = orders.iter()
+        .filter(|(_, state)| **state == OrderState::New)
+        .map(|(order, _)| *order)
+        .collect();
+    if new_orders.is_empty() || units.is_empty() {
+        return;
+    }
+    for (order, unit) in assigner.assign(&new_orders, &units) {
+        send_order(network_unit, delivery_tx, order, unit);
+        orders.insert(order, OrderState::Pending { unit, delivered_at: None });
     }
 }
 
 // Recieves external communcations and processes based on the comm_type
-fn receive_message(internal_order_channel_tx:Sender<InternalCommunication>, message: Communication) -> () {
-    if message.target == MASTER {
+// orders keeps the lifecycle of every hall order that is not served yet
+// Cleared hall calls are sent to every unit, so they are handled whatever their target is
+fn receive_message(order_memory_tx:Sender<MemoryRequest>, orders: &mut BTreeMap<Order, OrderState>, message: Communication, now: Instant) -> () {
+    if message.target == Target::Role(Role::Master) || message.comm_type == MessageKind::HallCallCleared {
         match message.comm_type {
-            STATUS_MESSAGE => { // handled on the network unit
+            MessageKind::Status => { // handled on the network unit
             }
-            ORDER_TRANSFER => {
+            MessageKind::OrderTransfer => {
                 println!("Order transfer recieved: {:#?}",message.order);
-                let new_comm = InternalCommunication {
-                    intention: INSERT,
-                    order: Some(message.order.unwrap())
-                };
-                internal_order_channel_tx.send(new_comm).unwrap();
+                let order = message.order.unwrap();
+                orders.entry(order).or_insert(OrderState::New);
+                order_memory_tx.send(MemoryRequest::Insert(order)).unwrap();
+            }
+            MessageKind::OrderAck => { // The unit that acknowledged has the order, even if it was given to another one since
+                let order = message.order.unwrap();
+                if !orders.contains_key(&order) { // Already served
+                    return;
+                }
+                orders.insert(order, OrderState::Acknowledged { unit: message.sender, since: now });
+                order_memory_tx.send(MemoryRequest::Assigned(order)).unwrap();
+            }
+            MessageKind::HallCallCleared => { // Order is done, no matter who had it
+                let order = message.order.unwrap();
+                orders.remove(&order);
+                order_memory_tx.send(MemoryRequest::Delete(order)).unwrap();
+            }
+            MessageKind::HallLights => { // Meant for the elevators
+            }
+            MessageKind::BackupInsert|MessageKind::BackupDelete => { // Meant for the backup
             }
-            ORDER_ACK => { // Sends message to order memory in order to delete acknowledged order.
-                let new_comm = InternalCommunication {
-                    intention: DELETE,
-                    order: message.order
+            MessageKind::BackupInsertAck => { // Backup has stored the order
+                order_memory_tx.send(MemoryRequest::ConfirmBackupInsert(message.order.unwrap())).unwrap();
+            }
+            MessageKind::BackupDeleteAck => { // Backup has removed the order
+                order_memory_tx.send(MemoryRequest::ConfirmBackupDelete(message.order.unwrap())).unwrap();
+            }
+            MessageKind::UnitDead => { // Dead units are left out of assignment through the state list
+                println!("Unit {} died", message.sender);
+                release_orders(&order_memory_tx, orders, message.sender);
+            }
+            MessageKind::UnitAlive => {
+                println!("Unit {} joined", message.sender);
+            }
+        }
+    }
+}
+
+// Sends a change in the order list to the master backup. The network layer reports whether it got there.
+fn send_to_backup(network_unit: &NetworkUnit, delivery_tx: &Sender<DeliveryReport>, comm_type: MessageKind, order: Order) -> () {
+    let new_message = Communication {
+        sender: u8::MAX,
+        sender_role: None,
+        target: Target::Role(Role::MasterBackup),
+        comm_type: comm_type,
+        status: None,
+        order: Some(order),
+        hall_lights: None
+    };
+    network_unit.send_reliable(new_message, delivery_tx);
+}
+
+// A button is lit once its order is acknowledged by a unit and stored on the backup, if there is one,
+// and stays lit until the order is served. The lit buttons are sent to every unit.
+fn update_hall_lights(
+    comms_channel_tx: &Sender<Communication>,
+    order_memory_tx: &Sender<MemoryRequest>,
+    orders: &BTreeMap<Order, OrderState>,
+    lit: &mut HashSet<Order>,
+    backup_id: Option<u8>
+) -> () {
+    let backed_up = ask_order_memory(order_memory_tx, MemoryRequest::BackedUp);
+    lit.retain(|order| orders.contains_key(order));
+    for (order, state) in orders {
+        if matches!(state, OrderState::Acknowledged { .. }) && (backup_id.is_none() || backed_up.contains(order)) {
+            lit.insert(*order);
+        }
+    }
+    send_hall_lights(comms_channel_tx, lit);
+}
+
+// Tells every unit which hall buttons should be lit
+fn send_hall_lights(comms_channel_tx: &Sender<Communication>, lit: &HashSet<Order>) -> () {
+    let new_message = Communication {
+        sender: u8::MAX,
+        sender_role: None,
+        target: Target::All,
+        comm_type: MessageKind::HallLights,
+        status: None,
+        order: None,
+        hall_lights: Some(Destinations::from_orders(lit))
+    };
+    comms_channel_tx.send(new_message).unwrap();
+}
+
+// Order memory that keeps a list of unassigned orders to be edited and read through message passing.
+// It also remembers the assigned orders that are not served yet, so the master backup gets all of them.
+// While there is a master backup, every change is also sent to it until the backup acknowledges it.
+fn order_memory(
+    order_memory_rx: Receiver<MemoryRequest>,
+    network_unit: NetworkUnit,
+    mut order_list: HashSet<Order>
+) -> () {
+    // Changes the backup has not acknowledged yet, stored as (BackupInsert/BackupDelete, order)
+    let mut backup_pending: HashSet<(MessageKind, Order)> = HashSet::new();
+    // Nothing is sent to the backup before run_master has found one, and asked for a resync
+    let mut has_backup = false;
+    let mut assigned_list: HashSet<Order> = HashSet::new();
+    let (delivery_tx, delivery_rx) = cbc::unbounded::<DeliveryReport>();
+    loop {
+        cbc::select! {
+            recv(order_memory_rx) -> a => {
+                // The channel closes when run_master returns
+                let Ok(request) = a else {
+                    return;
                 };
-                internal_order_channel_tx.send(new_comm).unwrap();
+                match request {
+                    MemoryRequest::Insert(order) => { // add
+                        order_list.insert(order);
+                        assigned_list.remove(&order);
+                        backup_pending.remove(&(MessageKind::BackupDelete, order));
+                        if has_backup {
+                            backup_pending.insert((MessageKind::BackupInsert, order));
+                            send_to_backup(&network_unit, &delivery_tx, MessageKind::BackupInsert, order);
+                        }
+                    }
+                    MemoryRequest::Delete(order) => { // remove
+                        order_list.remove(&order);
+                        assigned_list.remove(&order);
+                        backup_pending.remove(&(MessageKind::BackupInsert, order));
+                        if has_backup {
+                            backup_pending.insert((MessageKind::BackupDelete, order));
+                            send_to_backup(&network_unit, &delivery_tx, MessageKind::BackupDelete, order);
+                        }
+                    }
+                    MemoryRequest::Assigned(order) => { // An elevator has the order, the backup keeps it until it is served
+                        if order_list.remove(&order) {
+                            assigned_list.insert(order);
+                        }
+                    }
+                    MemoryRequest::ConfirmBackupInsert(order) => {
+                        backup_pending.remove(&(MessageKind::BackupInsert, order));
+                    }
+                    MemoryRequest::ConfirmBackupDelete(order) => {
+                        backup_pending.remove(&(MessageKind::BackupDelete, order));
+                    }
+                    MemoryRequest::ResyncBackup => { // A new backup has no orders, so it needs all of them
+                        has_backup = true;
+                        backup_pending.clear();
+                        for order in order_list.union(&assigned_list) {
+                            backup_pending.insert((MessageKind::BackupInsert, *order));
+                            send_to_backup(&network_unit, &delivery_tx, MessageKind::BackupInsert, *order);
+                        }
+                    }
+                    MemoryRequest::BackupGone => {
+                        has_backup = false;
+                        backup_pending.clear();
+                    }
+                    // Whoever asked may have given up, so a failed reply is fine
+                    MemoryRequest::OrderList(reply_tx) => {
+                        let _ = reply_tx.send(order_list.clone());
+                    }
+                    MemoryRequest::BackedUp(reply_tx) => {
+                        let backed_up: HashSet<Order> = order_list.union(&assigned_list)
+                            .filter(|order| !backup_pending.contains(&(MessageKind::BackupInsert, **order)))
+                            .copied()
+                            .collect();
+                        let _ = reply_tx.send(backed_up);
+                    }
+                }
             }
-            3_u8..=u8::MAX => {
-                println!("Feil i meldingssending")
+            recv(delivery_rx) -> a => {
+                // The network layer has given up on a change. It is sent again while we still have a backup
+                // to send it to, otherwise the resync for the next backup takes care of it.
+                let (message, delivered) = a.unwrap();
+                let change = (message.comm_type, message.order.unwrap());
+                if !delivered && backup_pending.contains(&change) {
+                    send_to_backup(&network_unit, &delivery_tx, change.0, change.1);
+                }
             }
         }
     }
 }
 
-// Order memory that keeps a list of orders to be edited and read through message passing.
-fn order_memory(internal_order_channel_rx: Receiver<InternalCommunication>, order_list_tx: Sender<HashSet<Order>>) -> () {
+// Keeps a copy of the master's order list while we are master backup.
+// Returns the copy when we are promoted to master, so no orders are lost, or when the unit is stopped.
+pub fn run_backup(network_unit:NetworkUnit, comms_channel_tx: Sender<Communication>, comms_channel_rx: Receiver<Communication>) -> HashSet<Order> {
     let mut order_list: HashSet<Order> = HashSet::new();
     loop {
         cbc::select! {
-            recv(internal_order_channel_rx) -> a => {
-                let communication = a.unwrap();
-                match communication.intention {
-                    INSERT => { // add
-                        order_list.insert(communication.order.unwrap());
+            recv(comms_channel_rx) -> a => {
+                // The network threads are gone once the unit is stopped
+                let Ok(message) = a else {
+                    return order_list;
+                };
+                let order = message.order.unwrap();
+                let ack_type = match message.comm_type {
+                    MessageKind::BackupInsert => {
+                        order_list.insert(order);
+                        MessageKind::BackupInsertAck
                     }
-                    DELETE => { // remove
-                        order_list.remove(&communication.order.unwrap());
+                    MessageKind::BackupDelete => {
+                        order_list.remove(&order);
+                        MessageKind::BackupDeleteAck
                     }
-                    REQUEST_ORDER => {
-                        let order_list_copy = order_list.clone();
-                        order_list_tx.send(order_list_copy).unwrap();
+                    // Sent to every unit, so the order is gone here as well should the master die before it asks us
+                    MessageKind::HallCallCleared => {
+                        order_list.remove(&order);
+                        continue;
                     }
-                    2_u8..=5_u8|7_u8..=u8::MAX => {
-                        println!("Wrong message to memory")
+                    _ => {
+                        println!("Wrong message to backup");
+                        continue;
                     }
+                };
+                let ack = Communication {
+                    sender: u8::MAX,
+                    sender_role: None,
+                    target: Target::Role(Role::Master),
+                    comm_type: ack_type,
+                    status: None,
+                    order: Some(order),
+                    hall_lights: None
+                };
+                comms_channel_tx.send(ack).unwrap();
+            }
+            recv(network_unit.clock.after(Duration::from_millis(100))) -> _ => {
+                if network_unit.get_role() == Role::Master || network_unit.is_stopped() {
+                    println!("Taking over with {} backed up orders", order_list.len());
+                    return order_list;
                 }
             }
-            // default(Duration::from_millis(100)) => {
-            //     //Chiller
-            // }
         }
     }
 }
 
-// Master function. Runs forever (or till it panics)
-pub fn run_master(network_unit:NetworkUnit,comms_channel_tx: Sender<Communication>, comms_channel_rx: Receiver<Communication>) -> () {
+// Gives our orders to the master that took over from us, which assigns them again.
+// Orders it already has are left as they are.
+fn hand_over_orders(comms_channel_tx: &Sender<Communication>, orders: &BTreeSet<Order>) -> () {
+    for order in orders {
+        let new_message = Communication {
+            sender: u8::MAX,
+            sender_role: None,
+            target: Target::Role(Role::Master),
+            comm_type: MessageKind::OrderTransfer,
+            status: None,
+            order: Some(*order),
+            hall_lights: None
+        };
+        comms_channel_tx.send(new_message).unwrap();
+    }
+}
+
+// Master function. Runs until another master with a lower id is found, and we step down, or the unit is stopped.
+// The assigner decides which unit serves each hall order
+pub fn run_master(network_unit:NetworkUnit,comms_channel_tx: Sender<Communication>, comms_channel_rx: Receiver<Communication>, order_list: HashSet<Order>, assigner: &mut dyn Assigner) -> () {
+
+    // setting up internal memory channel. Answers come back on a channel sent along with each request.
+    let (order_memory_tx, order_memory_rx) = cbc::bounded(1);
 
-    // setting up internal memory channel
-    let (internal_order_channel_tx, internal_order_channel_rx) = cbc::bounded(1);
-    let (order_list_tx, order_list_rx) = cbc::bounded(1);
+    // The hall orders whose buttons are lit. The orders taken over from the backup were lit by the old master.
+    let mut lit: HashSet<Order> = order_list.clone();
 
     { // spawn order memory
-    spawn(move || order_memory(internal_order_channel_rx, order_list_tx));
+    let network_unit = network_unit.clone();
+    spawn(move || order_memory(order_memory_rx, network_unit, order_list));
     }
 
+    // The network layer tells us whether the orders we send reach their units
+    let (delivery_tx, delivery_rx) = cbc::unbounded::<DeliveryReport>();
+
+    // The master backup we last sent the order list to
+    let mut backup_id: Option<u8> = None;
+
+    // The lifecycle of every hall order that is not served yet
+    let mut orders: BTreeMap<Order, OrderState> = BTreeMap::new();
+
+    // Orders are moved along on a fixed tick, so the timeouts are checked while messages keep coming in
+    let order_ticker = network_unit.clock.tick(Duration::from_millis(500));
+
     // Main master loop
     loop {
         // Crossbeam channel runs the main functions of the master
-        // It constantly checks whether it has received a message and runs a standard function if it has waited too long
+        // It constantly checks whether it has received a message, and moves the orders along on every tick
         cbc::select! {
             // Get info from comms_channel and process according to status if it is meant for us
             recv(comms_channel_rx) -> a => {
-                let message = a.unwrap();
-                println!("MASTER Received message: {:#?}", message.comm_type);
-                let internal_order_channel_tx = internal_order_channel_tx.clone();
-                receive_message(internal_order_channel_tx, message);
+                // The network threads are gone once the unit is stopped
+                let Ok(message) = a else {
+                    return;
+                };
+                println!("Role::Master Received message: {:#?}", message.comm_type);
+                let comm_type = message.comm_type;
+                {
+                let order_memory_tx = order_memory_tx.clone();
+                receive_message(order_memory_tx, &mut orders, message, network_unit.clock.now());
+                }
+                // A new confirmation can light a button right away, so the light is not beaten by the elevator
+                if comm_type == MessageKind::OrderAck || comm_type == MessageKind::BackupInsertAck {
+                    update_hall_lights(&comms_channel_tx, &order_memory_tx, &orders, &mut lit, backup_id);
+                }
+            }
+            recv(delivery_rx) -> a => {
+                let (message, delivered) = a.unwrap();
+                order_delivered(&order_memory_tx, &mut orders, message, delivered, network_unit.clock.now());
             }
-            // This function polls continuously if no other functions have been called
-            default(Duration::from_millis(500)) => {
-                // Opening status list for reading
-                let state_list = network_unit.get_state_list();
+            recv(order_ticker) -> _ => {
+                if network_unit.is_stopped() {
+                    return;
+                }
+                // After a partition has healed, the master with the lowest id is the only one left
+                if network_unit.get_role() != Role::Master {
+                    let mut handed_over: BTreeSet<Order> = orders.keys().copied().collect();
+                    handed_over.extend(ask_order_memory(&order_memory_tx, MemoryRequest::OrderList));
+                    println!("No longer master, handing over {} orders", handed_over.len());
+                    hand_over_orders(&comms_channel_tx, &handed_over);
+                    return;
+                }
 
-                // If status has been received, ie. elevator is alive, try to send orders
-                if !state_list.is_empty() {
-                    // Requesting order list from order memory
-                    let request = InternalCommunication {
-                        intention: REQUEST_ORDER,
-                        order: None
-                    };
-                    internal_order_channel_tx.send(request).unwrap();
-                    let order_list = order_list_rx.recv().unwrap();
-                    
-                    // Calling ordering function
-                    if !order_list.is_empty() {
-                        println!("Ordering up with order_list{:#?}", order_list);
-                        let comms_channel_tx = comms_channel_tx.clone();
-                        order_up(comms_channel_tx, order_list, state_list);
+                // Opening status list for reading, dead units can't take orders
+                let state_list: HashSet<State> = network_unit.get_alive_states().into_iter().collect();
+
+                // Units with errors, obstructions or the stop button pressed can't serve their orders, so they are given to the others
+                for state in &state_list {
+                    if !state.status.available() {
+                        release_orders(&order_memory_tx, &mut orders, state.id);
                     }
                 }
+                let state_list: HashSet<State> = state_list.into_iter()
+                    .filter(|s| s.status.available())
+                    .collect();
+
+                // If the backup has changed, it needs the whole order list.
+                // Right after a takeover our own last status still says we are the backup, so we are left out.
+                let current_backup = network_unit.get_alive_states().iter()
+                    .find(|s| s.role == Role::MasterBackup && s.id != network_unit.id)
+                    .map(|s| s.id);
+                if current_backup != backup_id {
+                    backup_id = current_backup;
+                    if backup_id.is_some() {
+                        order_memory_tx.send(MemoryRequest::ResyncBackup).unwrap();
+                    } else {
+                        order_memory_tx.send(MemoryRequest::BackupGone).unwrap();
+                    }
+                }
+
+                // Requesting order list from order memory
+                let order_list = ask_order_memory(&order_memory_tx, MemoryRequest::OrderList);
+
+                // Orders in the memory we have not seen are new, like the ones taken over from the backup.
+                // Unacknowledged orders that are gone from the memory have been served.
+                for order in &order_list {
+                    orders.entry(*order).or_insert(OrderState::New);
+                }
+                orders.retain(|order, state| matches!(state, OrderState::Acknowledged { .. }) || order_list.contains(order));
+
+                // If status has been received, ie. elevator is alive, try to send orders
+                if !state_list.is_empty() && !orders.is_empty() {
+                    order_up(&network_unit, &delivery_tx, &order_memory_tx, &mut orders, state_list, assigner);
+                }
+
+                update_hall_lights(&comms_channel_tx, &order_memory_tx, &orders, &mut lit, backup_id);
                 // println!("{:#?}", status_list);
             }
         }
@@ -0,0 +1,235 @@
+use crate::prelude::*;
+use crate::client::hardware::ElevatorHardware;
+use crate::clock::*;
+
+// How far from a floor, in floors, the floor sensor still sees it
+const SENSOR_WIDTH: f64 = 0.25;
+
+// Something the elevator did to the simulated hardware, kept so tests can check it afterwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HardwareEvent {
+    Motor(u8),
+    Door { open: bool, floor: Option<u8> }, // floor is what the floor sensor saw when the door changed
+    Light { floor: u8, call: u8, on: bool },
+    PowerOff,
+}
+
+// An elevator shaft simulated in the same process, so the elevator can be tested without the hardware server.
+// The car moves one floor per travel_time while the motor runs, and stops at the ends of the shaft.
+// Clones share the same shaft, like clones of the real driver share the same connection.
+#[derive(Clone, Debug)]
+pub struct SimulatedElevator {
+    shaft: Arc<Mutex<Shaft>>,
+    num_floors: u8,
+    generation: u64, // Handles from before the last power off no longer control the shaft
+}
+
+#[derive(Debug)]
+struct Shaft {
+    clock: Arc<dyn Clock>,
+    travel_time: Duration,
+    position: f64, // In floors, 0.0 is the bottom floor
+    motor: u8,
+    motor_failure: bool, // The car stands still whatever the motor is told
+    last_update: Instant,
+    call_lights: Vec<[bool; 3]>,
+    pressed: HashSet<(u8, u8)>, // Buttons pressed but not read yet
+    door_light: bool,
+    stop_light: bool,
+    floor_indicator: u8,
+    stop_button: bool,
+    obstruction: bool,
+    generation: u64,
+    history: Vec<(Instant, HardwareEvent)>,
+}
+
+impl Shaft {
+    // Moves the car according to the time passed since the last update
+    fn update(&mut self, num_floors: u8) {
+        let now = self.clock.now();
+        let floors_moved = now.duration_since(self.last_update).as_secs_f64() / self.travel_time.as_secs_f64();
+        self.last_update = now;
+        if self.motor_failure {
+            return;
+        }
+        match self.motor {
+            e::DIRN_UP => self.position += floors_moved,
+            e::DIRN_DOWN => self.position -= floors_moved,
+            _ => {}
+        }
+        self.position = self.position.clamp(0.0, (num_floors - 1) as f64);
+    }
+    fn floor_sensor(&self) -> Option<u8> {
+        let nearest = self.position.round();
+        if (self.position - nearest).abs() <= SENSOR_WIDTH {
+            return Some(nearest as u8);
+        }
+        return None;
+    }
+    fn record(&mut self, event: HardwareEvent) {
+        self.history.push((self.clock.now(), event));
+    }
+}
+
+impl SimulatedElevator {
+    pub fn new(num_floors: u8, travel_time: Duration, start_floor: u8) -> Self {
+        return SimulatedElevator::with_clock(num_floors, travel_time, start_floor, Arc::new(SystemClock));
+    }
+    // A shaft where the car moves as the clock goes, such as a manual clock in the scenario tests
+    pub fn with_clock(num_floors: u8, travel_time: Duration, start_floor: u8, clock: Arc<dyn Clock>) -> Self {
+        let shaft = Shaft {
+            last_update: clock.now(),
+            clock,
+            travel_time,
+            position: start_floor as f64,
+            motor: e::DIRN_STOP,
+            motor_failure: false,
+            call_lights: vec![[false; 3]; num_floors as usize],
+            pressed: HashSet::new(),
+            door_light: false,
+            stop_light: false,
+            floor_indicator: start_floor,
+            stop_button: false,
+            obstruction: false,
+            generation: 0,
+            history: Vec::new(),
+        };
+        SimulatedElevator {
+            shaft: Arc::new(Mutex::new(shaft)),
+            num_floors,
+            generation: 0,
+        }
+    }
+
+    fn shaft(&self) -> MutexGuard<'_, Shaft> {
+        let mut shaft = self.shaft.lock().unwrap();
+        shaft.update(self.num_floors);
+        return shaft;
+    }
+    // The shaft, if this handle still controls it
+    fn connected_shaft(&self) -> Option<MutexGuard<'_, Shaft>> {
+        let shaft = self.shaft();
+        if shaft.generation != self.generation {
+            return None;
+        }
+        return Some(shaft);
+    }
+
+    // Cuts the power, as when the unit running the elevator crashes. The car stops, the lights go out,
+    // and every handle given out so far stops working, so the threads of the crashed unit can't touch the shaft.
+    pub fn power_off(&self) {
+        let mut shaft = self.shaft();
+        shaft.generation += 1;
+        shaft.motor = e::DIRN_STOP;
+        shaft.door_light = false;
+        shaft.stop_light = false;
+        for lights in shaft.call_lights.iter_mut() {
+            *lights = [false; 3];
+        }
+        shaft.pressed.clear();
+        shaft.record(HardwareEvent::PowerOff);
+    }
+    // Returns a new handle that controls the shaft, for the unit started after a power off
+    pub fn power_on(&self) -> SimulatedElevator {
+        let generation = self.shaft().generation;
+        SimulatedElevator {
+            shaft: self.shaft.clone(),
+            num_floors: self.num_floors,
+            generation,
+        }
+    }
+
+    // Presses a button, it is seen by the next read of call_button
+    pub fn press_button(&self, floor: u8, call: u8) {
+        self.shaft().pressed.insert((floor, call));
+    }
+    pub fn set_stop_button(&self, pressed: bool) {
+        self.shaft().stop_button = pressed;
+    }
+    pub fn set_obstruction(&self, obstructed: bool) {
+        self.shaft().obstruction = obstructed;
+    }
+    pub fn set_motor_failure(&self, failed: bool) {
+        self.shaft().motor_failure = failed;
+    }
+
+    pub fn position(&self) -> f64 {
+        self.shaft().position
+    }
+    pub fn motor(&self) -> u8 {
+        self.shaft().motor
+    }
+    pub fn door_open(&self) -> bool {
+        self.shaft().door_light
+    }
+    pub fn call_light(&self, floor: u8, call: u8) -> bool {
+        self.shaft().call_lights[floor as usize][call as usize]
+    }
+    pub fn stop_light(&self) -> bool {
+        self.shaft().stop_light
+    }
+    pub fn floor_indicator_value(&self) -> u8 {
+        self.shaft().floor_indicator
+    }
+    pub fn floor(&self) -> Option<u8> {
+        self.shaft().floor_sensor()
+    }
+    // Everything the elevator has done to the hardware, oldest first
+    pub fn history(&self) -> Vec<(Instant, HardwareEvent)> {
+        self.shaft().history.clone()
+    }
+}
+
+impl ElevatorHardware for SimulatedElevator {
+    fn num_floors(&self) -> u8 {
+        self.num_floors
+    }
+    fn motor_direction(&self, dirn: u8) {
+        if let Some(mut shaft) = self.connected_shaft() {
+            shaft.motor = dirn;
+            shaft.record(HardwareEvent::Motor(dirn));
+        }
+    }
+    fn call_button_light(&self, floor: u8, call: u8, on: bool) {
+        if let Some(mut shaft) = self.connected_shaft() {
+            shaft.call_lights[floor as usize][call as usize] = on;
+            shaft.record(HardwareEvent::Light { floor, call, on });
+        }
+    }
+    fn floor_indicator(&self, floor: u8) {
+        if let Some(mut shaft) = self.connected_shaft() {
+            shaft.floor_indicator = floor;
+        }
+    }
+    fn door_light(&self, on: bool) {
+        if let Some(mut shaft) = self.connected_shaft() {
+            shaft.door_light = on;
+            let floor = shaft.floor_sensor();
+            shaft.record(HardwareEvent::Door { open: on, floor });
+        }
+    }
+    fn stop_button_light(&self, on: bool) {
+        if let Some(mut shaft) = self.connected_shaft() {
+            shaft.stop_light = on;
+        }
+    }
+    fn call_button(&self, floor: u8, call: u8) -> bool {
+        match self.connected_shaft() {
+            Some(mut shaft) => shaft.pressed.remove(&(floor, call)),
+            None => false,
+        }
+    }
+    fn floor_sensor(&self) -> Option<u8> {
+        self.connected_shaft().and_then(|shaft| shaft.floor_sensor())
+    }
+    fn stop_button(&self) -> bool {
+        self.connected_shaft().is_some_and(|shaft| shaft.stop_button)
+    }
+    fn obstruction(&self) -> bool {
+        self.connected_shaft().is_some_and(|shaft| shaft.obstruction)
+    }
+    // The simulated shaft never fails
+    fn reconnect(&self) -> bool {
+        true
+    }
+}
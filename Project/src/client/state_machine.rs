@@ -0,0 +1,385 @@
+use crate::prelude::*;
+use crate::client::cost::*;
+use crate::client::door::*;
+use crate::client::utils::*;
+use crate::process_pair::PairState;
+
+// What the elevator is doing
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Behaviour {
+    Idle, // At a floor with the door closed and nothing to do
+    Moving, // The motor runs in `direction`
+    DoorOpen, // At a floor serving orders, until the door has closed again
+    Stopped, // The stop button is pressed
+    Fault(ElevatorError), // Hall orders are left to the others. The motor is left as it is, so we can reach a floor again.
+}
+
+// Everything that can happen to the elevator: the hardware, the master and the clock
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ElevatorEvent {
+    Floor(u8), // The floor sensor sees a new floor
+    CabCall(u8),
+    OrderAssigned(Order), // A hall order from the master
+    StopButton { pressed: bool, at_floor: bool }, // at_floor is whether the floor sensor sees a floor right now
+    Obstruction(bool),
+    DriverFailure(bool), // The hardware can't be reached (true), or has been connected to again (false)
+    Tick, // Time has passed, runs the door timer and the motor timeout
+}
+
+// What the elevator asks the I/O layer to do
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ElevatorAction {
+    Motor(u8),
+    DoorLight(bool),
+    StopLight(bool),
+    CabLight(u8, bool),
+    FloorIndicator(u8),
+    SaveCabOrders, // The cab orders have changed and have to be journaled
+    AckOrder(Order), // Tell the master we have taken the order
+    ClearHallCall(Order), // Tell every unit the hall call is served
+}
+
+// The elevator logic. It only changes its own state and returns what has to be done,
+// so the same events at the same times always give the same actions. It does not log either, run_elevator does.
+// The route follows the same rules as the cost function, so the master's estimates hold.
+#[derive(Clone, Debug)]
+pub struct ElevatorStateMachine {
+    pub behaviour: Behaviour,
+    pub floor: Option<u8>, // The last floor we were at
+    pub motor: u8, // What the motor was last told
+    pub direction: u8, // The way the route goes. Kept while the door is open, so we carry on the same way.
+    pub destinations: HashSet<Order>, // Cab orders and the hall orders we have taken
+    pub obstructed: bool, // The obstruction switch has kept the door open for too long
+    door: Door,
+    obstruction: bool,
+    resume_direction: u8, // Where to go when the stop button is released between floors
+    moving_since: Instant, // When the motor started, or we last reached a floor
+    num_floors: u8,
+    motor_timeout: Duration,
+}
+
+impl ElevatorStateMachine {
+    pub fn new(num_floors: u8, door_open_time: Duration, motor_timeout: Duration, now: Instant) -> Self {
+        ElevatorStateMachine {
+            behaviour: Behaviour::Idle,
+            floor: None,
+            motor: e::DIRN_STOP,
+            direction: e::DIRN_STOP,
+            destinations: HashSet::new(),
+            obstructed: false,
+            door: Door::new(door_open_time, now),
+            obstruction: false,
+            resume_direction: e::DIRN_STOP,
+            moving_since: now,
+            num_floors,
+            motor_timeout,
+        }
+    }
+
+    // Starts the elevator with the cab orders from the journal. The elevator goes down until it finds a floor,
+    // unless we took over from a primary going up, in which case we take over its destinations as well.
+    pub fn start(&mut self, cab_orders: HashSet<Order>, restored_state: Option<PairState>, now: Instant) -> Vec<ElevatorAction> {
+        let mut actions = Vec::new();
+        for order in &cab_orders {
+            actions.push(ElevatorAction::CabLight(order.floor_number, true));
+        }
+        self.destinations = cab_orders;
+
+        let mut start_direction = e::DIRN_DOWN;
+        // The floor of the primary is not taken over, as the car may have moved since. The first floor we see
+        // would look like a jump from it.
+        if let Some(state) = restored_state {
+            if state.direction == e::DIRN_UP {
+                start_direction = e::DIRN_UP;
+            }
+            self.destinations.extend(state.destination_list);
+        }
+        self.start_moving(start_direction, now, &mut actions);
+        return actions;
+    }
+
+    pub fn handle(&mut self, event: ElevatorEvent, now: Instant) -> Vec<ElevatorAction> {
+        let mut actions = Vec::new();
+        match event {
+            ElevatorEvent::Floor(floor) => self.floor_reached(floor, now, &mut actions),
+            ElevatorEvent::CabCall(floor) => self.cab_call(floor, now, &mut actions),
+            ElevatorEvent::OrderAssigned(order) => self.order_assigned(order, now, &mut actions),
+            ElevatorEvent::StopButton { pressed: true, at_floor } => self.stop_pressed(at_floor, now, &mut actions),
+            ElevatorEvent::StopButton { pressed: false, at_floor } => self.stop_released(at_floor, now, &mut actions),
+            ElevatorEvent::Obstruction(obstruction) => self.obstruction = obstruction,
+            ElevatorEvent::DriverFailure(true) => self.fault(ElevatorError::DriverFailure),
+            ElevatorEvent::DriverFailure(false) => self.reconnected(&mut actions), // The fault clears when we see a floor again
+            ElevatorEvent::Tick => self.tick(now, &mut actions),
+        }
+        return actions;
+    }
+
+    // The cab orders among the destinations, as they are saved to the journal
+    pub fn cab_orders(&self) -> HashSet<Order> {
+        return self.destinations.iter().filter(|order| order.is_cab()).copied().collect();
+    }
+
+    pub fn door_state(&self) -> DoorState {
+        return self.door.state;
+    }
+
+    // The status the other units see
+    pub fn status(&self) -> Status {
+        let last_floor = self.floor.unwrap_or(self.num_floors + 1);
+        let error = match self.behaviour {
+            Behaviour::Fault(error) => Some(error),
+            _ => None,
+        };
+        return Status {
+            last_floor: last_floor,
+            direction: self.motor,
+            error: error,
+            obstructions: self.obstructed,
+            stopped: self.behaviour == Behaviour::Stopped,
+            target_floor: target_floor_function(self.motor, self.destinations.clone(), last_floor),
+            destinations: Destinations::from_orders(&self.destinations)
+        };
+    }
+
+    fn floor_reached(&mut self, floor: u8, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        let jumped = self.floor.is_some_and(|last| floor.abs_diff(last) > 1);
+        self.floor = Some(floor);
+        self.moving_since = now;
+        actions.push(ElevatorAction::FloorIndicator(floor));
+
+        // The sensor can't skip a floor, so it can't be trusted. We keep going, but not past the ends of the shaft.
+        if jumped {
+            self.fault(ElevatorError::FloorJump);
+            if self.at_end(floor, self.motor) {
+                self.set_motor(e::DIRN_STOP, actions);
+            }
+            return;
+        }
+        // Any other floor means the elevator works
+        if let Behaviour::Fault(_) = self.behaviour {
+            if self.motor == e::DIRN_STOP {
+                self.behaviour = Behaviour::Idle;
+            } else {
+                self.behaviour = Behaviour::Moving;
+                self.direction = self.motor;
+            }
+        }
+
+        match self.behaviour {
+            Behaviour::Moving if should_stop(&self.destinations, i32::from(floor), self.direction) || self.at_end(floor, self.direction) => {
+                self.set_motor(e::DIRN_STOP, actions);
+                self.serve_or_continue(now, actions);
+            }
+            Behaviour::Idle => self.serve_or_continue(now, actions),
+            _ => {}
+        }
+    }
+
+    fn cab_call(&mut self, floor: u8, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        // A call for the floor we are serving just keeps the door open
+        if self.behaviour == Behaviour::DoorOpen && self.floor == Some(floor) {
+            self.open_door(now, actions);
+            return;
+        }
+        let order = Order {
+            floor_number: floor,
+            call: CallKind::Cab
+        };
+        if self.destinations.insert(order) {
+            actions.push(ElevatorAction::SaveCabOrders);
+        }
+        actions.push(ElevatorAction::CabLight(floor, true));
+        if self.behaviour == Behaviour::Idle {
+            self.serve_or_continue(now, actions);
+        }
+    }
+
+    // Hall orders are only taken while we can serve them. Without an ack the master gives them to someone else.
+    fn order_assigned(&mut self, order: Order, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        match self.behaviour {
+            Behaviour::Stopped | Behaviour::Fault(_) => {} // Not acked, so the master gives it to someone else
+            _ => {
+                self.destinations.insert(order);
+                actions.push(ElevatorAction::AckOrder(order));
+                if self.behaviour == Behaviour::Idle {
+                    self.serve_or_continue(now, actions);
+                }
+            }
+        }
+    }
+
+    // The stop button halts the elevator at once and holds the door open if we are at a floor.
+    // Hall orders are left to the others, since our status says we are stopped.
+    fn stop_pressed(&mut self, at_floor: bool, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        if self.behaviour == Behaviour::Stopped {
+            return;
+        }
+        self.resume_direction = self.motor;
+        self.set_motor(e::DIRN_STOP, actions);
+        actions.push(ElevatorAction::StopLight(true));
+        self.destinations.retain(|order| order.is_cab());
+        self.behaviour = Behaviour::Stopped;
+        if at_floor {
+            self.open_door(now, actions);
+        }
+    }
+
+    // When the button is released the door closes as usual and the route goes on. Between floors we carry on
+    // the way we were going, or down if we were standing still, so the cab orders are served as before.
+    fn stop_released(&mut self, at_floor: bool, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        if self.behaviour != Behaviour::Stopped {
+            return;
+        }
+        actions.push(ElevatorAction::StopLight(false));
+        if at_floor {
+            if self.door.state == DoorState::Closed {
+                self.behaviour = Behaviour::Idle;
+                self.serve_or_continue(now, actions);
+            } else {
+                self.behaviour = Behaviour::DoorOpen;
+            }
+        } else {
+            let mut direction = self.resume_direction;
+            if direction == e::DIRN_STOP {
+                direction = e::DIRN_DOWN;
+            }
+            self.start_moving(direction, now, actions);
+        }
+    }
+
+    fn tick(&mut self, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        let keep_open = self.obstruction || self.behaviour == Behaviour::Stopped;
+        if let Some(light) = self.door.update(now, keep_open) {
+            actions.push(ElevatorAction::DoorLight(light));
+        }
+        let obstructed = self.door.prolonged_obstruction(now, self.obstruction);
+        if obstructed != self.obstructed {
+            self.obstructed = obstructed;
+        }
+
+        match self.behaviour {
+            Behaviour::DoorOpen if self.door.state == DoorState::Closed => self.serve_or_continue(now, actions),
+            Behaviour::Idle if !self.destinations.is_empty() => self.serve_or_continue(now, actions),
+            Behaviour::Moving if now.duration_since(self.moving_since) >= self.motor_timeout => {
+                self.fault(ElevatorError::MotorTimeout);
+            }
+            // The hardware can't be driven until we are connected again, and the floor we see then clears the fault
+            Behaviour::Fault(ElevatorError::DriverFailure) => {}
+            // Standing still with a fault, we have to reach a floor to clear it. The cab orders show the way,
+            // and without any we head for the middle of the shaft, which is away from the end we stopped at.
+            Behaviour::Fault(_) if self.motor == e::DIRN_STOP && self.door.state == DoorState::Closed => {
+                let direction = match self.floor {
+                    Some(floor) => match choose_direction(&self.destinations, i32::from(floor), e::DIRN_STOP) {
+                        e::DIRN_STOP if floor < self.num_floors / 2 => e::DIRN_UP,
+                        e::DIRN_STOP => e::DIRN_DOWN,
+                        direction => direction,
+                    },
+                    None => e::DIRN_DOWN,
+                };
+                self.direction = direction;
+                self.moving_since = now;
+                self.set_motor(direction, actions);
+            }
+            _ => {}
+        }
+    }
+
+    // Serves the orders at this floor, or else sets off for the next one, or else goes idle.
+    // Only called while standing at a floor.
+    fn serve_or_continue(&mut self, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        let Some(floor) = self.floor else {
+            return;
+        };
+        let mut remaining = self.destinations.clone();
+        clear_at_floor(&mut remaining, i32::from(floor), self.direction);
+        if remaining.len() < self.destinations.len() {
+            let mut cab_served = false;
+            for order in self.destinations.difference(&remaining) {
+                if order.is_cab() {
+                    cab_served = true;
+                } else {
+                    actions.push(ElevatorAction::ClearHallCall(*order));
+                }
+            }
+            if cab_served {
+                actions.push(ElevatorAction::CabLight(floor, false));
+                actions.push(ElevatorAction::SaveCabOrders);
+            }
+            self.destinations = remaining;
+            self.behaviour = Behaviour::DoorOpen;
+            self.open_door(now, actions);
+            return;
+        }
+
+        let direction = choose_direction(&self.destinations, i32::from(floor), self.direction);
+        if direction == e::DIRN_STOP {
+            self.behaviour = Behaviour::Idle;
+            self.direction = e::DIRN_STOP;
+        } else {
+            self.start_moving(direction, now, actions);
+        }
+    }
+
+    // The elevator is never moved unless the door is closed
+    fn start_moving(&mut self, direction: u8, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        if self.door.state != DoorState::Closed {
+            return;
+        }
+        self.behaviour = Behaviour::Moving;
+        self.direction = direction;
+        self.moving_since = now;
+        self.set_motor(direction, actions);
+    }
+
+    // The door is never opened unless the motor is stopped
+    fn open_door(&mut self, now: Instant, actions: &mut Vec<ElevatorAction>) -> () {
+        self.set_motor(e::DIRN_STOP, actions);
+        if self.door.open(now) {
+            actions.push(ElevatorAction::DoorLight(true));
+        }
+    }
+
+    fn set_motor(&mut self, direction: u8, actions: &mut Vec<ElevatorAction>) -> () {
+        if self.motor != direction {
+            self.motor = direction;
+            actions.push(ElevatorAction::Motor(direction));
+        }
+    }
+
+    // The hall orders are dropped when a fault starts, and the master gives them to the others once our status shows it.
+    // Faults while the stop button is pressed are left to be found again after it is released.
+    fn fault(&mut self, error: ElevatorError) -> () {
+        if self.behaviour == Behaviour::Stopped {
+            return;
+        }
+        if !matches!(self.behaviour, Behaviour::Fault(_)) {
+            self.destinations.retain(|order| order.is_cab());
+        }
+        self.behaviour = Behaviour::Fault(error);
+    }
+
+    // The car may have moved while we could not see it, so the next floor is not compared with the last one
+    fn reconnected(&mut self, actions: &mut Vec<ElevatorAction>) -> () {
+        self.rewrite_hardware(actions);
+        self.floor = None;
+    }
+
+    // Writes everything we show on the hardware again, as it may have been lost while the hardware could not be reached
+    fn rewrite_hardware(&self, actions: &mut Vec<ElevatorAction>) -> () {
+        actions.push(ElevatorAction::Motor(self.motor));
+        actions.push(ElevatorAction::DoorLight(self.door.state == DoorState::Open));
+        actions.push(ElevatorAction::StopLight(self.behaviour == Behaviour::Stopped));
+        let cab_orders = self.cab_orders();
+        for floor in 0..self.num_floors {
+            actions.push(ElevatorAction::CabLight(floor, cab_orders.iter().any(|order| order.floor_number == floor)));
+        }
+        if let Some(floor) = self.floor {
+            actions.push(ElevatorAction::FloorIndicator(floor));
+        }
+    }
+
+    // Whether going on in the direction would take us out of the shaft
+    fn at_end(&self, floor: u8, direction: u8) -> bool {
+        return (direction == e::DIRN_UP && floor + 1 >= self.num_floors) || (direction == e::DIRN_DOWN && floor == 0);
+    }
+}
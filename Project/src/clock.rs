@@ -0,0 +1,121 @@
+use crate::prelude::*;
+
+// Where a unit gets the time from. Every timer, sleep and timeout of a unit goes through its clock,
+// so a simulated cluster can run on a clock that is moved forward by hand instead of waiting.
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+    // Sends the time once, after the duration. Like crossbeam_channel::after.
+    fn after(&self, duration: Duration) -> Receiver<Instant>;
+    // Sends the time every period. Like crossbeam_channel::tick, ticks are skipped while the last one is not read.
+    fn tick(&self, period: Duration) -> Receiver<Instant>;
+}
+
+// The time of the computer, for the real elevators
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep(&self, duration: Duration) {
+        sleep(duration)
+    }
+    fn after(&self, duration: Duration) -> Receiver<Instant> {
+        cbc::after(duration)
+    }
+    fn tick(&self, period: Duration) -> Receiver<Instant> {
+        cbc::tick(period)
+    }
+}
+
+// A clock that only moves when it is advanced. Sleeps and timers end once it has been advanced past them.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    time: Arc<(Mutex<ManualTime>, Condvar)>,
+}
+
+#[derive(Debug)]
+struct ManualTime {
+    now: Instant,
+    timers: Vec<Timer>,
+}
+
+#[derive(Debug)]
+struct Timer {
+    at: Instant,
+    period: Option<Duration>, // Timers from tick go off again, the ones from after only once
+    tx: Sender<Instant>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        let time = ManualTime { now: Instant::now(), timers: Vec::new() };
+        return ManualClock { time: Arc::new((Mutex::new(time), Condvar::new())) };
+    }
+
+    // Moves the time forward, and wakes everything that was waiting for it
+    pub fn advance(&self, duration: Duration) {
+        let (time, moved) = &*self.time;
+        let mut time = time.lock().unwrap();
+        time.now += duration;
+        let now = time.now;
+        time.timers.retain_mut(|timer| {
+            if timer.at > now {
+                return true;
+            }
+            // A full channel just means the last tick has not been read yet
+            if let Err(cbc::TrySendError::Disconnected(_)) = timer.tx.try_send(timer.at) {
+                return false;
+            }
+            let Some(period) = timer.period else {
+                return false;
+            };
+            while timer.at <= now {
+                timer.at += period;
+            }
+            return true;
+        });
+        moved.notify_all();
+    }
+
+    fn add_timer(&self, duration: Duration, period: Option<Duration>) -> Receiver<Instant> {
+        let (tx, rx) = cbc::bounded(1);
+        let mut time = self.time.0.lock().unwrap();
+        let at = time.now + duration;
+        time.timers.push(Timer { at, period, tx });
+        return rx;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.time.0.lock().unwrap().now
+    }
+    fn sleep(&self, duration: Duration) {
+        let (time, moved) = &*self.time;
+        let mut time = time.lock().unwrap();
+        let wake_up = time.now + duration;
+        while time.now < wake_up {
+            time = moved.wait(time).unwrap();
+        }
+    }
+    fn after(&self, duration: Duration) -> Receiver<Instant> {
+        if duration.is_zero() {
+            let (tx, rx) = cbc::bounded(1);
+            tx.send(self.now()).unwrap();
+            return rx;
+        }
+        return self.add_timer(duration, None);
+    }
+    fn tick(&self, period: Duration) -> Receiver<Instant> {
+        return self.add_timer(period, Some(period));
+    }
+}
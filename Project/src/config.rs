@@ -0,0 +1,145 @@
+use crate::prelude::*;
+use crate::client::assigner::AssignerKind;
+
+// Settings for one elevator unit. Read from a JSON config file and/or the command line,
+// where the command line overrides the file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub id: u8,
+    pub listen_port: u16,
+    pub broadcast_addr: String,
+    pub driver_addr: String,
+    pub num_floors: u8,
+    pub heartbeat_timeout_ms: u64, // Time without a status before a unit is considered dead
+    pub startup_wait_ms: u64, // Time spent listening for other units before choosing a role
+    pub door_open_ms: u64,
+    pub travel_time_ms: u64, // Time the elevator takes between two floors, used to estimate the cost of orders
+    pub motor_timeout_ms: u64, // Time the motor may run without reaching a new floor before it is a fault
+    pub assigner: AssignerKind, // How the master decides which unit serves a hall order
+    pub cab_journal: Option<String>, // File the cab orders are saved to
+    pub process_pair: bool, // Run with a backup process that takes over if this one dies
+    pub pair_port: Option<u16>, // Local port the backup listens for heartbeats on
+    #[serde(skip)]
+    pub backup: bool, // Set on the backup process, which waits for the primary to die
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            id: 10,
+            listen_port: 20010,
+            broadcast_addr: String::from("255.255.255.255:20010"),
+            driver_addr: String::from("localhost:15657"),
+            num_floors: 4,
+            heartbeat_timeout_ms: 3000,
+            startup_wait_ms: 3000,
+            door_open_ms: 3000,
+            travel_time_ms: 2500,
+            motor_timeout_ms: 10000,
+            assigner: AssignerKind::TimeToIdle,
+            cab_journal: None,
+            process_pair: false,
+            pair_port: None,
+            backup: false,
+        }
+    }
+}
+
+impl Config {
+    // Builds the config from the program arguments, e.g.
+    // ttk4145-project --config elevator.json --id 2 --driver localhost:15658
+    pub fn from_args(args: &[String]) -> anyhow::Result<Config> {
+        let mut config = Config::default();
+
+        // Read the config file first, so the other arguments can override it
+        if let Some(i) = args.iter().position(|a| a == "--config") {
+            let path = args.get(i + 1).ok_or_else(|| anyhow!("--config needs a file name"))?;
+            let text = std::fs::read_to_string(path)?;
+            config = serde_json::from_str(&text)?;
+        }
+
+        let mut i = 1;
+        while i < args.len() {
+            // Flags without a value
+            match args[i].as_str() {
+                "--process-pair" => {
+                    config.process_pair = true;
+                    i += 1;
+                    continue;
+                }
+                "--backup" => {
+                    config.process_pair = true;
+                    config.backup = true;
+                    i += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            let value = args.get(i + 1).ok_or_else(|| anyhow!("Missing value for {}", args[i]))?;
+            match args[i].as_str() {
+                "--config" => {}
+                "--id" => config.id = value.parse()?,
+                "--port" => config.listen_port = value.parse()?,
+                "--broadcast" => config.broadcast_addr = value.clone(),
+                "--driver" => config.driver_addr = value.clone(),
+                "--floors" => config.num_floors = value.parse()?,
+                "--timeout" => config.heartbeat_timeout_ms = value.parse()?,
+                "--startup-wait" => config.startup_wait_ms = value.parse()?,
+                "--door-time" => config.door_open_ms = value.parse()?,
+                "--travel-time" => config.travel_time_ms = value.parse()?,
+                "--motor-timeout" => config.motor_timeout_ms = value.parse()?,
+                "--assigner" => config.assigner = value.parse()?,
+                "--journal" => config.cab_journal = Some(value.clone()),
+                "--pair-port" => config.pair_port = Some(value.parse()?),
+                other => return Err(anyhow!("Unknown argument {}", other)),
+            }
+            i += 2;
+        }
+        // The destinations of an elevator are bit masks with one bit per floor
+        if config.num_floors == 0 || config.num_floors > Destinations::MAX_FLOORS {
+            return Err(anyhow!("The number of floors must be between 1 and {}, not {}", Destinations::MAX_FLOORS, config.num_floors));
+        }
+        return Ok(config);
+    }
+
+    pub fn listen_addr(&self) -> String {
+        format!("0.0.0.0:{}", self.listen_port)
+    }
+
+    // Each unit gets its own journal by default, so several units can run from the same folder
+    pub fn cab_journal_path(&self) -> String {
+        match &self.cab_journal {
+            Some(path) => path.clone(),
+            None => format!("cab_orders_{}.json", self.id),
+        }
+    }
+
+    // Each unit gets its own pair port by default, for the same reason
+    pub fn pair_port(&self) -> u16 {
+        match self.pair_port {
+            Some(port) => port,
+            None => 20100 + self.id as u16,
+        }
+    }
+
+    pub fn heartbeat_timeout(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_timeout_ms)
+    }
+
+    pub fn startup_wait(&self) -> Duration {
+        Duration::from_millis(self.startup_wait_ms)
+    }
+
+    pub fn door_open_time(&self) -> Duration {
+        Duration::from_millis(self.door_open_ms)
+    }
+
+    pub fn travel_time(&self) -> Duration {
+        Duration::from_millis(self.travel_time_ms)
+    }
+
+    pub fn motor_timeout(&self) -> Duration {
+        Duration::from_millis(self.motor_timeout_ms)
+    }
+}
@@ -1,9 +1,22 @@
 pub mod network {
     pub mod server;
+    pub mod transport;
 }
 pub mod client {
     pub mod elevator;
+    pub mod state_machine;
+    pub mod door;
     pub mod master;
+    pub mod cost;
+    pub mod assigner;
     pub mod utils;
+    pub mod journal;
+    pub mod hardware;
+    pub mod simulator;
 }
-pub mod prelude;
\ No newline at end of file
+pub mod prelude;
+pub mod config;
+pub mod clock;
+pub mod process_pair;
+pub mod node;
+pub mod scenario;
\ No newline at end of file
@@ -1,187 +1,525 @@
 use crate::prelude::*;
-use std::net::UdpSocket;
-use std::str;
+use crate::config::Config;
+use crate::network::transport::*;
+use crate::clock::*;
+use std::collections::BTreeSet;
 
-const BROADCAST_ADDR: &str = "255.255.255.255:20010";
-const LISTEN_ADDR: &str = "0.0.0.0:20010";
-pub const ID: u8 = 10;
+// Time a unit has to be dead before it is removed from the state list
+const PRUNE_TIMEOUT: Duration = Duration::from_secs(30);
+const WATCHDOG_PERIOD: Duration = Duration::from_millis(200);
+
+// How often our newest status is broadcast
+const STATUS_PERIOD: Duration = Duration::from_millis(200);
+
+// Retransmission of messages that have not been acknowledged, the wait doubles each time
+const FIRST_RETRY: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_millis(2000);
+const MAX_ATTEMPTS: u32 = 10;
+// Number of sequence numbers remembered per sender for duplicate suppression
+const SEEN_WINDOW: usize = 1000;
+
+// What is actually sent over the network. Data packets to a unit or a role are acknowledged by the
+// receiver and resent until they are, data packets to all units are sent once.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Packet {
+    Data { seq: u64, reliable: bool, message: Communication },
+    Ack { from: u8, to: u8, seq: u64 },
+}
+
+// A sent message, and whether its target acknowledged it before the network layer gave up
+pub type DeliveryReport = (Communication, bool);
+
+// A message waiting to be sent, with an optional channel to report delivery on
+type Outgoing = (Communication, Option<Sender<DeliveryReport>>);
+
+// A sent packet waiting for its acknowledgement
+struct PendingPacket {
+    packet: Packet,
+    attempts: u32,
+    backoff: Duration,
+    next_send: Instant,
+    report_tx: Option<Sender<DeliveryReport>>
+}
 
 #[derive(Clone, Debug)]
 pub struct NetworkUnit {
     pub id: u8,
-    pub role: u8,
-    pub my_master: Option<u8>,
-    pub state_list: Arc<Mutex<HashSet<State>>>
+    pub role: Arc<Mutex<Role>>,
+    pub my_master: Arc<Mutex<Option<u8>>>,
+    pub state_list: Arc<Mutex<HashSet<State>>>,
+    pub heartbeat_timeout: Duration,
+    pub transport: Arc<dyn Transport>,
+    pub clock: Arc<dyn Clock>, // Every thread of the unit keeps time with this
+    pub stopped: Arc<atomic::AtomicBool>, // Set when the unit is shut down, every thread of the unit stops when it sees it
+    outbox_tx: Sender<Outgoing>,
+    outbox_rx: Receiver<Outgoing>,
+    ack_tx: Sender<u64>, // Sequence numbers acknowledged to us, from the receiver to the sender
+    ack_rx: Receiver<u64>
 }
 
 impl NetworkUnit {
-    pub fn new(id:u8) -> Self {
+    // A unit on the real network, using the addresses in the config
+    pub fn new(config: &Config) -> Self {
+        let transport = UdpTransport::new(&config.listen_addr(), &config.broadcast_addr);
+        return NetworkUnit::with_transport(config, Arc::new(transport), Arc::new(SystemClock));
+    }
+    pub fn with_transport(config: &Config, transport: Arc<dyn Transport>, clock: Arc<dyn Clock>) -> Self {
+        let (outbox_tx, outbox_rx) = cbc::unbounded::<Outgoing>();
+        let (ack_tx, ack_rx) = cbc::unbounded::<u64>();
         NetworkUnit {
-            id,
-            role: MASTER,
-            my_master: None,
+            id: config.id,
+            role: Arc::new(Mutex::new(Role::Master)),
+            my_master: Arc::new(Mutex::new(None)),
             state_list: Arc::new(Mutex::new(HashSet::new())),
+            heartbeat_timeout: config.heartbeat_timeout(),
+            transport,
+            clock,
+            stopped: Arc::new(atomic::AtomicBool::new(false)),
+            outbox_tx,
+            outbox_rx,
+            ack_tx,
+            ack_rx,
+        }
+    }
+    // Sends a message and reports on report_tx whether its target acknowledged it, once the network layer
+    // knows. Messages to all units are reported as delivered once sent.
+    pub fn send_reliable(&self, message: Communication, report_tx: &Sender<DeliveryReport>) {
+        self.outbox_tx.send((message, Some(report_tx.clone()))).unwrap();
+    }
+    // Whether a message to the target should be handled, and acknowledged, by us
+    fn is_for_me(&self, target: Target) -> bool {
+        match target {
+            Target::Unit(id) => id == self.id,
+            Target::Role(role) => role == self.get_role(),
+            Target::All => true,
         }
     }
-    pub fn update_state_list(&self, new_state: State) {
+    // Inserts or replaces the state of a unit. Returns true if the unit is new or was dead.
+    pub fn update_state_list(&self, new_state: State) -> bool {
         let mut state_list = self.state_list.lock().unwrap();
+        let mut rejoined = true;
         // Remove existing state with the same id
         if let Some(existing) = state_list.iter().find(|s| s.id == new_state.id).cloned() {
+            rejoined = !existing.alive;
             state_list.remove(&existing);
         }
         state_list.insert(new_state);
+        return rejoined;
     }
     pub fn get_state_list(&self) -> HashSet<State> {
         self.state_list.lock().unwrap().clone()
     }
-    pub fn update_role(&mut self) {
-        let state_list = self.state_list.lock().unwrap();
-        let has_master = state_list.iter().any(|s| s.role == MASTER);
-        let has_master_backup = state_list.iter().any(|s| s.role == MASTER_BACKUP);
+    // Returns the states of the units that are alive, including ourselves
+    pub fn get_alive_states(&self) -> Vec<State> {
+        self.get_state_list().into_iter()
+            .filter(|s| s.alive)
+            .collect()
+    }
+    // Returns the states of the other units that are alive
+    fn get_alive_peers(&self) -> Vec<State> {
+        self.get_alive_states().into_iter()
+            .filter(|s| s.id != self.id)
+            .collect()
+    }
+    // Marks units we have not heard from within the timeout as dead, and removes units that have been dead for long.
+    // Returns the ids of the units that died.
+    pub fn check_liveness(&self) -> Vec<u8> {
+        let mut state_list = self.state_list.lock().unwrap();
+        let mut dead_units = Vec::new();
+        let old_list: Vec<State> = state_list.drain().collect();
+        for mut state in old_list {
+            let elapsed = self.clock.now().saturating_duration_since(state.last_seen);
+            if elapsed >= PRUNE_TIMEOUT {
+                println!("Removing unit {} from state list", state.id);
+                continue;
+            }
+            if state.alive && elapsed >= self.heartbeat_timeout {
+                println!("Unit {} stopped responding", state.id);
+                state.alive = false;
+                dead_units.push(state.id);
+            }
+            state_list.insert(state);
+        }
+        return dead_units;
+    }
+    // Stops all the threads of the unit, as when the program is killed
+    pub fn stop(&self) {
+        self.stopped.store(true, atomic::Ordering::Relaxed);
+    }
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(atomic::Ordering::Relaxed)
+    }
+    pub fn get_role(&self) -> Role {
+        *self.role.lock().unwrap()
+    }
+    pub fn get_master(&self) -> Option<u8> {
+        *self.my_master.lock().unwrap()
+    }
+    fn set_role(&self, new_role: Role, master_id: Option<u8>) {
+        *self.role.lock().unwrap() = new_role;
+        *self.my_master.lock().unwrap() = master_id;
+    }
+    pub fn update_role(&self) {
+        let state_list = self.get_alive_peers();
+        let has_master = state_list.iter().any(|s| s.role == Role::Master);
+        let has_master_backup = state_list.iter().any(|s| s.role == Role::MasterBackup);
     
         let (new_role, master_id) = if !has_master {
             // No master found, become master
-            (MASTER, Some(self.id))
+            (Role::Master, Some(self.id))
         } else if has_master && !has_master_backup {
             // Master exists but no backup, become master_backup
             let master_id = state_list.iter()
-                .find(|s| s.role == MASTER)
+                .find(|s| s.role == Role::Master)
                 .map(|s| s.id);
-            (MASTER_BACKUP, master_id)
+            (Role::MasterBackup, master_id)
         } else {
             // Both master and backup exist, become slave
             let master_id = state_list.iter()
-                .find(|s| s.role == MASTER)
+                .find(|s| s.role == Role::Master)
                 .map(|s| s.id);
-            (SLAVE, master_id)
+            (Role::Slave, master_id)
         };
         
-        self.role = new_role;
-        self.my_master = master_id;
+        self.set_role(new_role, master_id);
+    }
+    // Checks the heartbeats of the other units and takes over roles that have gone silent.
+    // When a partition heals there can be two masters or two backups, and the one with the lowest id keeps its role.
+    pub fn check_heartbeats(&self) {
+        let state_list = self.get_alive_peers();
+        let master = state_list.iter().filter(|s| s.role == Role::Master).map(|s| s.id).min();
+        let has_master_backup = state_list.iter().any(|s| s.role == Role::MasterBackup);
+        let lower_backup = state_list.iter().any(|s| s.role == Role::MasterBackup && s.id < self.id);
+        // Only the slave with the lowest id steps up, so two slaves don't take the same role
+        let first_slave = state_list.iter()
+            .filter(|s| s.role == Role::Slave)
+            .all(|s| s.id > self.id);
+
+        match self.get_role() {
+            Role::Master => {
+                if master.is_some_and(|id| id < self.id) {
+                    println!("Unit {} is also master, stepping down", master.unwrap());
+                    self.set_role(Role::Slave, master);
+                }
+            }
+            Role::MasterBackup => {
+                if master.is_none() {
+                    println!("Master stopped responding, promoting self to master");
+                    self.set_role(Role::Master, Some(self.id));
+                } else if lower_backup {
+                    println!("Another unit is also master backup, stepping down");
+                    self.set_role(Role::Slave, master);
+                } else if master != self.get_master() {
+                    self.set_role(Role::MasterBackup, master);
+                }
+            }
+            Role::Slave => {
+                if master.is_none() && !has_master_backup && first_slave {
+                    println!("Master and backup stopped responding, promoting self to master");
+                    self.set_role(Role::Master, Some(self.id));
+                } else if master.is_some() && !has_master_backup && first_slave {
+                    println!("No master backup found, promoting self to master backup");
+                    self.set_role(Role::MasterBackup, master);
+                } else if master.is_some() && master != self.get_master() {
+                    self.set_role(Role::Slave, master);
+                }
+            }
+        }
+    }
+}
+
+// Tells the master that a unit has died or come back, so it can move orders around
+fn send_unit_event(network_unit: &NetworkUnit, master_channel_tx: &Sender<Communication>, id: u8, comm_type: MessageKind) {
+    if network_unit.get_role() == Role::Master {
+        let event = Communication {
+            sender: id,
+            sender_role: None,
+            target: Target::Role(Role::Master),
+            comm_type: comm_type,
+            status: None,
+            order: None,
+            hall_lights: None
+        };
+        let _ = master_channel_tx.send(event);
     }
 }
 
+// Watches the heartbeats of the other units for as long as the unit runs
+pub fn network_watchdog(network_unit: NetworkUnit, master_channel_tx: Sender<Communication>) {
+    while !network_unit.is_stopped() {
+        network_unit.clock.sleep(WATCHDOG_PERIOD);
+        for id in network_unit.check_liveness() {
+            send_unit_event(&network_unit, &master_channel_tx, id, MessageKind::UnitDead);
+        }
+        network_unit.check_heartbeats();
+    }
+}
+
+fn send_packet(network_unit: &NetworkUnit, packet: &Packet) -> std::io::Result<()> {
+    let json = serde_json::to_string(packet)?;
+    network_unit.transport.broadcast(json.as_bytes())?;
+    return Ok(());
+}
+
+// Gives a message its sequence number and sends it. Messages that need an acknowledgement are kept for resending.
+fn send_message(
+    network_unit: &NetworkUnit,
+    outgoing: Outgoing,
+    next_seq: &mut u64,
+    pending: &mut HashMap<u64, PendingPacket>
+) -> std::io::Result<()> {
+    let (mut msg, report_tx) = outgoing;
+
+    // Statuses and hall lights are sent again every round, so they are broadcast as they are. Other messages to all
+    // units are sent to every unit we know of, ourselves included, so each copy is acknowledged and resent if it is lost.
+    let periodic = msg.comm_type == MessageKind::Status || msg.comm_type == MessageKind::HallLights;
+    if msg.target == Target::All && !periodic {
+        let mut ids: BTreeSet<u8> = network_unit.get_alive_states().iter().map(|s| s.id).collect();
+        ids.insert(network_unit.id);
+        for id in ids {
+            let mut copy = msg;
+            copy.target = Target::Unit(id);
+            send_message(network_unit, (copy, None), next_seq, pending)?;
+        }
+        if let Some(report_tx) = report_tx {
+            let _ = report_tx.send((msg, true));
+        }
+        return Ok(());
+    }
+
+    msg.sender = network_unit.id;
+    msg.sender_role = Some(network_unit.get_role());
+    // println!("Sending message: {:#?}", msg);
+    let reliable = msg.target != Target::All;
+    let packet = Packet::Data { seq: *next_seq, reliable, message: msg };
+
+    if reliable {
+        pending.insert(*next_seq, PendingPacket {
+            packet: packet.clone(),
+            attempts: 1,
+            backoff: FIRST_RETRY,
+            next_send: network_unit.clock.now() + FIRST_RETRY,
+            report_tx
+        });
+    } else if let Some(report_tx) = report_tx {
+        let _ = report_tx.send((msg, true));
+    }
+    *next_seq += 1;
+
+    return send_packet(network_unit, &packet);
+}
+
+// Tells the sender of a packet whether it was acknowledged, if it asked to know
+fn report_delivery(done: PendingPacket, delivered: bool) {
+    if let (Some(report_tx), Packet::Data { message, .. }) = (done.report_tx, done.packet) {
+        let _ = report_tx.send((message, delivered));
+    }
+}
+
+// Sends everything on the network channel as soon as it arrives. Status messages are not queued; only the newest
+// one is kept, and it is broadcast on a fixed heartbeat so order traffic never waits behind statuses.
 pub fn network_periodic_sender(network_unit: NetworkUnit, network_channel_rx: Receiver<Communication>) {
-    loop {
-        if let Ok(socket) = UdpSocket::bind("0.0.0.0:0")
-            .and_then(|s| s.set_broadcast(true).map(|_| s)) 
-        {
-            let mut restart = false;
-            let mut current_message: Option<Communication> = None; // <-- Mutable holder
-            
-            while !restart {
-                // Check for new messages first
-                if let Ok(msg) = network_channel_rx.try_recv() {
-                    let mut msg = msg;
-                    msg.sender = network_unit.id;
-                    msg.sender_role = network_unit.role;
-                    // println!("Sending message: {:#?}", msg);
-                    current_message = Some(msg);
+    // Sequence numbers start at the current time, so the packets of a restarted unit are not taken for duplicates
+    let mut next_seq: u64 = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+    let mut pending: HashMap<u64, PendingPacket> = HashMap::new();
+    let mut latest_status: Option<Communication> = None;
+    let clock = network_unit.clock.clone();
+    let mut next_status = clock.now();
+    while !network_unit.is_stopped() {
+        // Wait for new messages, but not past the next heartbeat or resend
+        let mut wake_up = next_status;
+        for waiting in pending.values() {
+            wake_up = min(wake_up, waiting.next_send);
+        }
+        let mut outgoing: Vec<Outgoing> = Vec::new();
+        cbc::select! {
+            recv(network_unit.outbox_rx) -> a => outgoing.push(a.unwrap()),
+            recv(network_channel_rx) -> a => outgoing.extend(a.ok().map(|msg| (msg, None))), // Closed once the unit is stopped
+            // An acknowledgement wakes us up as well, so the delivery is reported right away
+            recv(network_unit.ack_rx) -> a => {
+                if let Some(done) = pending.remove(&a.unwrap()) {
+                    report_delivery(done, true);
                 }
+            }
+            recv(clock.after(wake_up.saturating_duration_since(clock.now()))) -> _ => {}
+        }
 
-                // Send the message if we have one
-                if let Some(msg) = &current_message {
-                    let json = match serde_json::to_string(msg) {
-                        Ok(j) => j,
-                        Err(e) => {
-                            eprintln!("JSON error: {}", e);
-                            restart = true;
-                            continue;
-                        }
-                    };
-                    
-                    if let Err(e) = socket.send_to(json.as_bytes(), BROADCAST_ADDR) {
-                        eprintln!("Send error: {}", e);
-                        restart = true;
-                    }
-                    
-                    current_message = None;
+        // Take everything else that is waiting, the ones waiting for a delivery report first
+        while let Ok(a) = network_unit.outbox_rx.try_recv() {
+            outgoing.push(a);
+        }
+        while let Ok(msg) = network_channel_rx.try_recv() {
+            outgoing.push((msg, None));
+        }
+
+        // Order traffic goes out right away, statuses wait for the heartbeat.
+        // A message that fails to send stays pending if it is reliable, so it is resent like a lost one.
+        for (msg, report_tx) in outgoing {
+            if msg.comm_type == MessageKind::Status && report_tx.is_none() {
+                latest_status = Some(msg);
+                continue;
+            }
+            if let Err(e) = send_message(&network_unit, (msg, report_tx), &mut next_seq, &mut pending) {
+                eprintln!("Send error: {}", e);
+            }
+        }
+
+        // Broadcast our newest status on every heartbeat, even if the elevator has not sent a new one
+        if clock.now() >= next_status {
+            next_status = clock.now() + STATUS_PERIOD;
+            if let Some(msg) = latest_status {
+                if let Err(e) = send_message(&network_unit, (msg, None), &mut next_seq, &mut pending) {
+                    eprintln!("Send error: {}", e);
                 }
+            }
+        }
 
-                sleep(Duration::from_millis(100));
+        // Acknowledged packets are done
+        while let Ok(seq) = network_unit.ack_rx.try_recv() {
+            if let Some(done) = pending.remove(&seq) {
+                report_delivery(done, true);
+            }
+        }
+
+        // Resend the packets that are still not acknowledged, and give up on the ones that have been sent too many times
+        let now = clock.now();
+        let mut failed = Vec::new();
+        for (seq, waiting) in pending.iter_mut() {
+            if now < waiting.next_send {
+                continue;
+            }
+            if waiting.attempts >= MAX_ATTEMPTS {
+                failed.push(*seq);
+                continue;
+            }
+            if let Err(e) = send_packet(&network_unit, &waiting.packet) {
+                eprintln!("Resend error: {}", e);
+            }
+            waiting.attempts += 1;
+            waiting.backoff = min(waiting.backoff * 2, MAX_BACKOFF);
+            waiting.next_send = now + waiting.backoff;
+        }
+        for seq in failed {
+            if let Some(lost) = pending.remove(&seq) {
+                println!("Message was never acknowledged: {:?}", lost.packet);
+                report_delivery(lost, false);
             }
         }
-        sleep(Duration::from_secs(1));
     }
 }
 
-pub fn network_receiver(network_unit: NetworkUnit, master_channel_tx:Sender<Communication>,elevator_channel_tx:Sender<Communication>) {
-    loop {
-        // Create new socket each iteration to recover from errors
-        let socket = match UdpSocket::bind(LISTEN_ADDR) {
-            Ok(s) => s,
+pub fn network_receiver(network_unit: NetworkUnit, master_channel_tx:Sender<Communication>,backup_channel_tx:Sender<Communication>,elevator_channel_tx:Sender<Communication>) {
+    // Sequence numbers we have already handled from each unit, so resent packets are only handled once
+    let mut seen: HashMap<u8, BTreeSet<u64>> = HashMap::new();
+    while !network_unit.is_stopped() {
+        let packet = match network_unit.transport.receive(Duration::from_secs(5)) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => continue, // Timeout occurred, continue waiting
             Err(e) => {
-                eprintln!("Failed to bind socket: {}, retrying...", e);
-                std::thread::sleep(Duration::from_secs(1));
+                eprintln!("Receive error: {}, retrying...", e);
+                network_unit.clock.sleep(Duration::from_secs(1));
                 continue;
             }
         };
 
-        // Set timeout for receiving
-        if let Err(e) = socket.set_read_timeout(Some(Duration::from_secs(5))) {
-            eprintln!("Failed to set timeout: {}, retrying...", e);
-            continue;
-        }
-
-        let mut buf = [0; 1024];
-            
-        loop {
-            // println!("RECIEVING:::");
-            match socket.recv_from(&mut buf) {
-                Ok((size, _)) => {
-                    // Attempt to parse the message
-                    if let Ok(received) = str::from_utf8(&buf[..size]) {
-                        // println!("received");
-                        if let Ok(msg) = serde_json::from_str::<Communication>(received) {
-                            // println!("recieved: {:#?}",msg);
-                            let network_unit = network_unit.clone();
-                            let master_channel_tx = master_channel_tx.clone();
-                            let elevator_channel_tx = elevator_channel_tx.clone();
-                            network_message_handler(network_unit,msg,master_channel_tx,elevator_channel_tx)
-                        }
+        // Attempt to parse the packet
+        match serde_json::from_slice::<Packet>(&packet) {
+            Ok(Packet::Data { seq, reliable, message }) => {
+                // Acknowledge every copy, in case our earlier acknowledgement was lost
+                if reliable {
+                    // Not remembered as seen either, since a resent copy may be for us once our role has changed
+                    if !network_unit.is_for_me(message.target) {
+                        continue;
+                    }
+                    let ack = Packet::Ack { from: network_unit.id, to: message.sender, seq };
+                    if let Err(e) = send_packet(&network_unit, &ack) {
+                        eprintln!("Ack send error: {}", e);
                     }
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    // Timeout occurred, continue waiting
-                    continue;
+
+                let window = seen.entry(message.sender).or_default();
+                if !window.insert(seq) {
+                    continue; // Duplicate
+                }
+                if window.len() > SEEN_WINDOW {
+                    window.pop_first();
                 }
-                Err(e) => {
-                    // eprintln!("Receive error: {}, restarting...", e);
-                    break;
+
+                // println!("recieved: {:#?}",message);
+                let network_unit = network_unit.clone();
+                let master_channel_tx = master_channel_tx.clone();
+                let backup_channel_tx = backup_channel_tx.clone();
+                let elevator_channel_tx = elevator_channel_tx.clone();
+                network_message_handler(network_unit,message,master_channel_tx,backup_channel_tx,elevator_channel_tx)
+            }
+            Ok(Packet::Ack { to, seq, .. }) => {
+                if to == network_unit.id {
+                    let _ = network_unit.ack_tx.send(seq);
                 }
             }
+            Err(_) => {}
         }
     }
 }
 
 // Recieves external network communcations and processes based on the comm_type
-fn network_message_handler(network_unit: NetworkUnit,message:Communication,master_channel_tx:Sender<Communication>,elevator_channel_tx:Sender<Communication>) {
+fn network_message_handler(network_unit: NetworkUnit,message:Communication,master_channel_tx:Sender<Communication>,backup_channel_tx:Sender<Communication>,elevator_channel_tx:Sender<Communication>) {
     // println!("Recieved message {:#?}", message);
     match message.target {
-        MASTER => {
-            if network_unit.role == MASTER {
+        Target::Role(Role::Master) => {
+            if network_unit.get_role() == Role::Master {
                 println!("Sent to master {:#?}", message);
                 let _ = master_channel_tx.send(message);
             }
         }
-        ID | TARGET_ALL => {
+        Target::Role(Role::MasterBackup) => {
+            if network_unit.get_role() == Role::MasterBackup {
+                let _ = backup_channel_tx.send(message);
+            }
+        }
+        Target::Role(Role::Slave) => {
+            // Nothing is sent to the slaves as a group
+        }
+        Target::Unit(id) if id != network_unit.id => {
+            // Meant for someone else
+        }
+        Target::Unit(_) | Target::All => {
             match message.comm_type {
-                STATUS_MESSAGE => {
-                    if let Some(status) = message.status {
+                MessageKind::Status => {
+                    if let (Some(status), Some(role)) = (message.status, message.sender_role) {
                         let new_state = State {
                             id: message.sender,
-                            role: message.sender_role,
+                            role: role,
                             status,
+                            last_seen: network_unit.clock.now(),
+                            alive: true,
                         };
-                        network_unit.update_state_list(new_state);
+                        if network_unit.update_state_list(new_state) {
+                            println!("Unit {} is alive", message.sender);
+                            send_unit_event(&network_unit, &master_channel_tx, message.sender, MessageKind::UnitAlive);
+                        }
                     }
                 }
-                ORDER_TRANSFER => {
+                MessageKind::OrderTransfer => {
+                    let _ = elevator_channel_tx.send(message);
+                }
+                MessageKind::HallLights => {
+                    let _ = elevator_channel_tx.send(message);
+                }
+                MessageKind::HallCallCleared => { // Every elevator clears its light, and the master and backup their order
+                    match network_unit.get_role() {
+                        Role::Master => {
+                            let _ = master_channel_tx.send(message);
+                        }
+                        Role::MasterBackup => {
+                            let _ = backup_channel_tx.send(message);
+                        }
+                        _ => {}
+                    }
                     let _ = elevator_channel_tx.send(message);
                 }
                 _ => {}
             }
         }
-        _ => {}
     }
 }
\ No newline at end of file
@@ -0,0 +1,273 @@
+use crate::prelude::*;
+use crate::clock::*;
+use std::net::{UdpSocket, SocketAddr, ToSocketAddrs};
+use std::collections::BinaryHeap;
+use std::cmp::Reverse;
+use std::io;
+
+// How the network unit reaches the other units. Every packet is broadcast, and every unit
+// receives every packet, including its own.
+pub trait Transport: Send + Sync + std::fmt::Debug {
+    fn broadcast(&self, data: &[u8]) -> io::Result<()>;
+    // Waits for the next packet, returns None if none arrived within the timeout
+    fn receive(&self, timeout: Duration) -> io::Result<Option<Vec<u8>>>;
+}
+
+// The real network. The sockets are bound on first use and thrown away after an error,
+// so they are bound again on the next call.
+// The receive socket shares its port, so several units can run on one host with the same listen address.
+#[derive(Debug)]
+pub struct UdpTransport {
+    listen_addr: String,
+    broadcast_addr: String,
+    send_socket: Mutex<Option<UdpSocket>>,
+    receive_socket: Mutex<Option<UdpSocket>>,
+}
+
+impl UdpTransport {
+    pub fn new(listen_addr: &str, broadcast_addr: &str) -> Self {
+        UdpTransport {
+            listen_addr: listen_addr.to_string(),
+            broadcast_addr: broadcast_addr.to_string(),
+            send_socket: Mutex::new(None),
+            receive_socket: Mutex::new(None),
+        }
+    }
+}
+
+// Binds a socket with SO_REUSEADDR and SO_REUSEPORT set, so other sockets can bind the same port.
+// Every socket on the port gets its own copy of each broadcast.
+fn bind_shared(listen_addr: &str) -> io::Result<UdpSocket> {
+    let addr: SocketAddr = listen_addr.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("No address for {}", listen_addr)))?;
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&addr.into())?;
+    return Ok(socket.into());
+}
+
+impl Transport for UdpTransport {
+    fn broadcast(&self, data: &[u8]) -> io::Result<()> {
+        let mut send_socket = self.send_socket.lock().unwrap();
+        if send_socket.is_none() {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.set_broadcast(true)?;
+            *send_socket = Some(socket);
+        }
+        let result = send_socket.as_ref().unwrap().send_to(data, self.broadcast_addr.as_str());
+        if result.is_err() {
+            *send_socket = None;
+        }
+        return result.map(|_| ());
+    }
+
+    fn receive(&self, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+        let mut receive_socket = self.receive_socket.lock().unwrap();
+        if receive_socket.is_none() {
+            *receive_socket = Some(bind_shared(&self.listen_addr)?);
+        }
+        let socket = receive_socket.as_ref().unwrap();
+        socket.set_read_timeout(Some(timeout))?;
+
+        let mut buf = [0; 1024];
+        match socket.recv_from(&mut buf) {
+            Ok((size, _)) => return Ok(Some(buf[..size].to_vec())),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+            Err(e) => {
+                *receive_socket = None;
+                return Err(e);
+            }
+        }
+    }
+}
+
+// Faults the in-memory bus puts on every packet. Probabilities are from 0.0 to 1.0.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Faults {
+    pub loss: f64,
+    pub duplication: f64,
+    pub reorder: f64, // Reordered packets are held back by reorder_delay, so later packets overtake them
+    pub delay_ms: u64,
+    pub reorder_delay_ms: u64,
+}
+
+// A network inside one process, so several units can run in the same test.
+// Packets can be lost, delayed, duplicated and reordered, and the units can be split into partitions.
+// Delays and receive timeouts go by the clock of the bus.
+#[derive(Clone, Debug)]
+pub struct MemoryBus {
+    bus: Arc<Mutex<Bus>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct Bus {
+    inboxes: HashMap<u8, Arc<Inbox>>, // The current connection of each unit
+    faults: Faults,
+    partitions: Vec<HashSet<u8>>, // Units in different partitions can't reach each other, units not listed reach everyone
+    rng: u64,
+    next_seq: u64,
+}
+
+// A packet on its way: when it arrives, the order it was sent in, and the data
+type InFlight = Reverse<(Instant, u64, Vec<u8>)>;
+
+// Packets on their way to a unit, ordered by when they arrive
+#[derive(Debug)]
+struct Inbox {
+    queue: Mutex<BinaryHeap<InFlight>>,
+    arrived_tx: Sender<()>, // Wakes up the receiver when a packet is added
+    arrived_rx: Receiver<()>,
+}
+
+impl Inbox {
+    fn new() -> Self {
+        let (arrived_tx, arrived_rx) = cbc::bounded(1);
+        Inbox { queue: Mutex::new(BinaryHeap::new()), arrived_tx, arrived_rx }
+    }
+}
+
+impl Bus {
+    // Xorshift, so a run with the same seed makes the same choices
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        return (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+    }
+    fn partition_of(&self, id: u8) -> Option<usize> {
+        self.partitions.iter().position(|p| p.contains(&id))
+    }
+    fn can_reach(&self, from: u8, to: u8) -> bool {
+        match (self.partition_of(from), self.partition_of(to)) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
+}
+
+impl MemoryBus {
+    pub fn new(seed: u64) -> Self {
+        return MemoryBus::with_clock(seed, Arc::new(SystemClock));
+    }
+    pub fn with_clock(seed: u64, clock: Arc<dyn Clock>) -> Self {
+        let bus = Bus {
+            inboxes: HashMap::new(),
+            faults: Faults::default(),
+            partitions: Vec::new(),
+            rng: max(seed, 1),
+            next_seq: 0,
+        };
+        MemoryBus { bus: Arc::new(Mutex::new(bus)), clock }
+    }
+    // Connects a unit to the bus. Connecting an id again replaces its old connection,
+    // like a restarted unit binding its socket again.
+    pub fn connect(&self, id: u8) -> MemoryTransport {
+        let inbox = Arc::new(Inbox::new());
+        self.bus.lock().unwrap().inboxes.insert(id, inbox.clone());
+        MemoryTransport { id, bus: self.clone(), inbox }
+    }
+    pub fn set_faults(&self, faults: Faults) {
+        self.bus.lock().unwrap().faults = faults;
+    }
+    // Splits the units into groups that can only reach the units in their own group
+    pub fn partition(&self, groups: Vec<Vec<u8>>) {
+        self.bus.lock().unwrap().partitions = groups.into_iter()
+            .map(|g| g.into_iter().collect())
+            .collect();
+    }
+    pub fn heal(&self) {
+        self.bus.lock().unwrap().partitions.clear();
+    }
+    // A disconnected unit neither sends nor receives anything, as if it had crashed
+    pub fn disconnect(&self, id: u8) {
+        self.bus.lock().unwrap().inboxes.remove(&id);
+    }
+
+    fn send(&self, from: u8, connection: &Arc<Inbox>, data: &[u8]) {
+        let mut bus = self.bus.lock().unwrap();
+        // Packets from a connection that has been replaced or disconnected go nowhere
+        match bus.inboxes.get(&from) {
+            Some(current) if Arc::ptr_eq(current, connection) => {}
+            _ => return,
+        }
+        let faults = bus.faults;
+        let mut receivers: Vec<(u8, Arc<Inbox>)> = bus.inboxes.iter()
+            .map(|(id, inbox)| (*id, inbox.clone()))
+            .collect();
+        receivers.sort_by_key(|(id, _)| *id);
+        for (to, inbox) in receivers {
+            if !bus.can_reach(from, to) {
+                continue;
+            }
+            // Our own packets never leave the unit, so they are not disturbed
+            let mut copies = 1;
+            if to != from {
+                if bus.random() < faults.loss {
+                    continue;
+                }
+                if bus.random() < faults.duplication {
+                    copies = 2;
+                }
+            }
+            for _ in 0..copies {
+                let mut delay = Duration::ZERO;
+                if to != from {
+                    delay = Duration::from_millis(faults.delay_ms);
+                    if bus.random() < faults.reorder {
+                        delay += Duration::from_millis(faults.reorder_delay_ms);
+                    }
+                }
+                let seq = bus.next_seq;
+                bus.next_seq += 1;
+                inbox.queue.lock().unwrap().push(Reverse((self.clock.now() + delay, seq, data.to_vec())));
+                let _ = inbox.arrived_tx.try_send(());
+            }
+        }
+    }
+}
+
+// One unit's connection to a MemoryBus
+#[derive(Clone, Debug)]
+pub struct MemoryTransport {
+    id: u8,
+    bus: MemoryBus,
+    inbox: Arc<Inbox>,
+}
+
+impl Transport for MemoryTransport {
+    fn broadcast(&self, data: &[u8]) -> io::Result<()> {
+        self.bus.send(self.id, &self.inbox, data);
+        return Ok(());
+    }
+
+    fn receive(&self, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+        let clock = &self.bus.clock;
+        let deadline = clock.now() + timeout;
+        loop {
+            let now = clock.now();
+            // Take the first packet if it has arrived, otherwise wait for it or for a new one
+            let mut wake_up = deadline;
+            {
+                let mut queue = self.inbox.queue.lock().unwrap();
+                if let Some(Reverse((arrival, _, _))) = queue.peek() {
+                    if *arrival <= now {
+                        let Reverse((_, _, data)) = queue.pop().unwrap();
+                        return Ok(Some(data));
+                    }
+                    wake_up = min(wake_up, *arrival);
+                }
+            }
+            if now >= deadline {
+                return Ok(None);
+            }
+            cbc::select! {
+                recv(self.inbox.arrived_rx) -> _ => {}
+                recv(clock.after(wake_up - now)) -> _ => {}
+            }
+        }
+    }
+}
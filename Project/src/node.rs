@@ -0,0 +1,111 @@
+use crate::prelude::*;
+use crate::config::Config;
+use crate::network::server::*;
+use crate::process_pair::PairState;
+use crate::client::hardware::ElevatorHardware;
+use crate::client::elevator::{run_elevator, ElevatorSettings, ElevatorChannels};
+use crate::client::assigner::new_assigner;
+
+// Poll period for buttons and sensors
+const POLL_PERIOD: Duration = Duration::from_millis(25);
+
+// Starts all the threads of one unit: network, master and elevator.
+// Returns once the unit has picked its starting role, the threads keep running after that until network_unit.stop().
+pub fn start_node<H: ElevatorHardware>(
+    config: &Config,
+    network_unit: NetworkUnit,
+    elevator: H,
+    restored_state: Option<PairState>,
+    pair_state_tx: Option<Sender<PairState>>
+) -> () {
+    // Set floor count
+    let elev_num_floors = config.num_floors;
+
+    // Set up communication channels between the network and the rest of the unit
+    let (network_send_channel_tx, network_send_channel_rx) = cbc::unbounded::<Communication>();
+    let (master_channel_tx, master_channel_rx) = cbc::unbounded::<Communication>();
+    let (backup_channel_tx, backup_channel_rx) = cbc::unbounded::<Communication>();
+    let (elevator_channel_tx, elevator_channel_rx) = cbc::unbounded::<Communication>();
+
+    // Initialize network reciever
+    {
+    let network_unit:NetworkUnit = network_unit.clone();
+    let master_channel_tx: Sender<Communication> = master_channel_tx.clone();
+    let backup_channel_tx: Sender<Communication> = backup_channel_tx.clone();
+    let elevator_channel_tx: Sender<Communication> = elevator_channel_tx.clone();
+    spawn(move || {network_receiver(network_unit, master_channel_tx,backup_channel_tx,elevator_channel_tx);});
+    }
+
+    // Listen for statuses a bit before determining starting role
+    {
+        network_unit.clock.sleep(config.startup_wait());
+        network_unit.update_role();
+        println!("Set role as {:?}:",network_unit.get_role())
+    }
+
+    // Initialize watchdog, which takes over the role of units that stop sending statuses
+    {
+        let network_unit:NetworkUnit = network_unit.clone();
+        let master_channel_tx: Sender<Communication> = master_channel_tx.clone();
+        spawn(move || {network_watchdog(network_unit, master_channel_tx);});
+    }
+
+    // Initialize network sender
+    {
+        let network_unit:NetworkUnit = network_unit.clone();
+        let network_send_channel_rx: Receiver<Communication> = network_send_channel_rx.clone();
+        spawn(move || {network_periodic_sender(network_unit,network_send_channel_rx);});
+    }
+
+    // New scope so cloned values only stay inside it
+    {
+    // Cloning critical variables
+    // Note that for all of these, cloning only creates a seperate handle, not a new variable
+    let network_unit=network_unit.clone();
+    let network_channel_tx = network_send_channel_tx.clone();
+    let master_channel_rx = master_channel_rx.clone();
+    let backup_channel_rx = backup_channel_rx.clone();
+    let mut assigner = new_assigner(config);
+    // Starting a thread which keeps a copy of the master's orders until we are master, either at startup
+    // or after the old master dies, and then runs the master with those orders.
+    // A master that steps down goes back to waiting.
+    spawn(move || {
+        while !network_unit.is_stopped() {
+            let order_list = crate::client::master::run_backup(network_unit.clone(), network_channel_tx.clone(), backup_channel_rx.clone());
+            if network_unit.is_stopped() {
+                break;
+            }
+            println!("Master initiated.");
+            crate::client::master::run_master(network_unit.clone(),network_channel_tx.clone(), master_channel_rx.clone(), order_list, assigner.as_mut());
+            println!("Stepped down as master");
+        }
+    });
+    }
+
+    // New scope so cloned values only stay inside it
+    {
+    // Cloning critical variables
+    // Note that for all of these, cloning only creates a seperate handle, not a new variable
+    let network_unit=network_unit.clone();
+    let settings = ElevatorSettings {
+        id: network_unit.id,
+        num_floors: elev_num_floors,
+        poll_period: POLL_PERIOD,
+        door_open_time: config.door_open_time(),
+        motor_timeout: config.motor_timeout(),
+        cab_journal: config.cab_journal_path(),
+        clock: network_unit.clock.clone(),
+    };
+    let channels = ElevatorChannels {
+        comms_channel_tx: network_send_channel_tx.clone(),
+        comms_channel_rx: elevator_channel_rx.clone(),
+        pair_state_tx,
+    };
+    let stopped = network_unit.stopped.clone();
+
+    // Starting a thread which runs the elevator and starts the necessary threads
+    spawn(move || {
+        run_elevator(elevator, settings, channels, restored_state, stopped);
+    });
+    }
+}
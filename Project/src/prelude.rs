@@ -3,9 +3,11 @@ pub use std::hash::Hash;
 pub use std::thread::*;
 pub use std::time::*;
 pub use std::collections::HashSet;
+pub use std::collections::HashMap;
 pub use std::u8;
 pub use std::sync::*;
 pub use std::cmp::max;
+pub use std::cmp::min;
 pub use std::ops::Not;
 
 pub use serde::{Serialize,Deserialize};
@@ -22,6 +24,7 @@ pub use driver_rust::elevio::elev::DIRN_STOP;
 pub use driver_rust::elevio::elev::DIRN_UP;
 pub use driver_rust::elevio::elev::HALL_DOWN;
 pub use driver_rust::elevio::elev::HALL_UP;
+pub use driver_rust::elevio::elev::CAB;
 pub use driver_rust::elevio::elev as e;
 pub use driver_rust::elevio::poll;
 pub use driver_rust::elevio::poll::floor_sensor;
@@ -31,28 +34,62 @@ pub use driver_rust::elevio::poll::CallButton;
 pub use cli_table::{format::Justify, print_stdout, Cell, Style, Table};
 pub use clearscreen;
 
-// Structure for a hall order
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, PartialOrd, Serialize, Deserialize)]
+// The button a call came from. Cab calls have no direction and are served from either side.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallKind {
+    HallUp,
+    HallDown,
+    Cab,
+}
+
+impl CallKind {
+    // The call for one of the driver's button consts (HALL_UP, HALL_DOWN or CAB), None for any other value
+    pub fn from_button(button: u8) -> Option<CallKind> {
+        match button {
+            HALL_UP => Some(CallKind::HallUp),
+            HALL_DOWN => Some(CallKind::HallDown),
+            CAB => Some(CallKind::Cab),
+            _ => None,
+        }
+    }
+    // The driver's button const for the call
+    pub fn button(&self) -> u8 {
+        match self {
+            CallKind::HallUp => HALL_UP,
+            CallKind::HallDown => HALL_DOWN,
+            CallKind::Cab => CAB,
+        }
+    }
+    // The way the elevator goes to serve the call. None for cab calls, which are served going either way.
+    pub fn direction(&self) -> Option<u8> {
+        match self {
+            CallKind::HallUp => Some(DIRN_UP),
+            CallKind::HallDown => Some(DIRN_DOWN),
+            CallKind::Cab => None,
+        }
+    }
+}
+
+// Structure for an order
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Order {
     pub floor_number: u8,
-    pub direction: u8
+    pub call: CallKind
 }
 
 impl Order {
-    pub fn new() -> Self {
-        Order{
-            floor_number: u8::MAX,
-            direction: DIRN_STOP
-        }
+    pub fn is_cab(&self) -> bool {
+        return self.call == CallKind::Cab;
     }
 }
 
 // Print an order for testing purposes
 pub fn print_order(order: &Order) -> () {
     let floor = order.floor_number;
-    let direction = order.direction;
+    let call = order.call;
     println!("Floor: \n{:#?}", floor);
-    println!("Direction: \n{:#?}", direction);
+    println!("Call: \n{:#?}", call);
 
 }
 
@@ -61,9 +98,11 @@ pub fn print_order(order: &Order) -> () {
 pub struct Status {
     pub last_floor: u8,
     pub direction: u8,
-    pub errors: bool, // Yes or no, any errors
+    pub error: Option<ElevatorError>, // The fault the elevator has found in itself, if any
     pub obstructions: bool, // Yes or no, any obstructions
-    pub target_floor: Option<u8>
+    pub stopped: bool, // The stop button is pressed
+    pub target_floor: Option<u8>,
+    pub destinations: Destinations
 }
 
 impl Status {
@@ -71,56 +110,122 @@ impl Status {
         Status{
             last_floor: u8::MAX,
             direction: u8::MAX,
-            errors: false,
+            error: None,
             obstructions: false,
-            target_floor: Some(u8::MAX)
+            stopped: false,
+            target_floor: Some(u8::MAX),
+            destinations: Destinations::default()
+        }
+    }
+    // Whether the unit can serve hall orders
+    pub fn available(&self) -> bool {
+        return self.error.is_none() && !self.obstructions && !self.stopped;
+    }
+}
+
+// Faults an elevator detects in itself. They clear once it reaches a floor again.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, PartialOrd, Serialize, Deserialize)]
+pub enum ElevatorError {
+    MotorTimeout, // The motor has run for the motor timeout without reaching a new floor
+    FloorJump, // The floor sensor skipped a floor
+    DriverFailure, // The hardware could not be read
+}
+
+// The destination list of an elevator as one bit per floor and call, so a Status can stay Copy
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default, PartialOrd, Serialize, Deserialize)]
+pub struct Destinations {
+    pub up: u16,
+    pub down: u16,
+    pub cab: u16
+}
+
+impl Destinations {
+    pub const MAX_FLOORS: u8 = 16;
+
+    pub fn from_orders(orders: &HashSet<Order>) -> Self {
+        let mut destinations = Destinations::default();
+        for order in orders {
+            if order.floor_number >= Destinations::MAX_FLOORS {
+                continue;
+            }
+            match order.call {
+                CallKind::HallUp => destinations.up |= 1 << order.floor_number,
+                CallKind::HallDown => destinations.down |= 1 << order.floor_number,
+                CallKind::Cab => destinations.cab |= 1 << order.floor_number,
+            }
         }
+        return destinations;
+    }
+
+    pub fn to_orders(&self) -> HashSet<Order> {
+        let mut orders = HashSet::new();
+        for floor in 0..Destinations::MAX_FLOORS {
+            if self.up & (1 << floor) != 0 {
+                orders.insert(Order { floor_number: floor, call: CallKind::HallUp });
+            }
+            if self.down & (1 << floor) != 0 {
+                orders.insert(Order { floor_number: floor, call: CallKind::HallDown });
+            }
+            if self.cab & (1 << floor) != 0 {
+                orders.insert(Order { floor_number: floor, call: CallKind::Cab });
+            }
+        }
+        return orders;
     }
 }
 
 // Structure for cross-module communication. (Eventually replaced by networking)
 #[derive(PartialEq, Eq, Hash, Copy, Clone, Debug,Serialize, Deserialize)]
 pub struct Communication {
-    pub sender: u8,
-    pub sender_role: u8,
-    pub target: u8,
-    pub comm_type: u8,
+    pub sender: u8, // Filled in by the network sender
+    pub sender_role: Option<Role>, // Filled in by the network sender
+    pub target: Target,
+    pub comm_type: MessageKind,
     pub status: Option<Status>,
-    pub order: Option<Order>
+    pub order: Option<Order>,
+    pub hall_lights: Option<Destinations> // Only in HallLights messages
 }
 
-// Const variables for use in comms
-pub const STATUS_MESSAGE: u8 = 0;
-pub const ORDER_TRANSFER: u8 = 1;
-pub const ORDER_ACK: u8 = 2;
-pub const TARGET_ALL: u8 = u8::MAX;
-
-// Structure for internal communications through message passing
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
-pub struct InternalCommunication {
-    pub intention: u8, // use code words defined below
-    pub order: Option<Order>
+// What a message is about
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum MessageKind {
+    Status,
+    OrderTransfer,
+    OrderAck,
+    HallCallCleared, // Sent from an elevator to all units when it has stopped for an order, so it is cleared everywhere
+    HallLights, // Broadcast by the master with the hall orders that are assigned and backed up, so every panel lights the same buttons
+    BackupInsert, // Master tells the backup an order was added
+    BackupDelete, // Master tells the backup an order was removed
+    BackupInsertAck,
+    BackupDeleteAck,
+    UnitDead, // Sent from the network unit to the master, sender is the dead unit
+    UnitAlive, // Sent from the network unit to the master, sender is the new or rejoined unit
 }
 
-// Const variables for use in internal comms
-pub const DELETE: u8 = 0;
-pub const INSERT: u8 = 1;
-pub const REQUEST_DESTINATION: u8 = 6;
-pub const REQUEST_ORDER: u8 = 6;
-pub const REQUEST_DIRECTION: u8 = 7;
-pub const UPDATE_DIRECTION: u8 = 8;
-
-pub const DIRN_STOP_TEMP: u8 = 3;
+// Who a message is meant for
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Target {
+    Unit(u8), // A single unit by id
+    Role(Role), // Whichever unit has the role
+    All,
+}
 
-// Const variables for networking
-pub const MASTER: u8 = 0;
-pub const MASTER_BACKUP: u8 = 1;
-pub const SLAVE: u8 = 2;
+// Roles of the units in the network
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, PartialOrd, Serialize, Deserialize)]
+pub enum Role {
+    Master,
+    MasterBackup,
+    Slave,
+}
 
 // Structure for the state of a network unit
 #[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, PartialOrd, Serialize, Deserialize)]
 pub struct State {
     pub id: u8,
-    pub role: u8,
+    pub role: Role,
     pub status: Status,
+    #[serde(skip, default = "Instant::now")]
+    pub last_seen: Instant, // When we last heard a status from this unit
+    #[serde(skip)]
+    pub alive: bool, // False once we have not heard from the unit within the heartbeat timeout
 }
\ No newline at end of file
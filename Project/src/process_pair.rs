@@ -0,0 +1,89 @@
+use crate::prelude::*;
+use std::net::UdpSocket;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+const PAIR_HEARTBEAT_PERIOD: Duration = Duration::from_millis(100);
+// Time without a heartbeat before the backup takes over
+const PAIR_TIMEOUT: Duration = Duration::from_millis(1500);
+
+// The elevator state the primary sends to its backup, enough to continue where it left off
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairState {
+    pub last_floor: u8,
+    pub direction: u8,
+    pub destination_list: HashSet<Order>,
+}
+
+fn pair_addr(pair_port: u16) -> String {
+    format!("127.0.0.1:{}", pair_port)
+}
+
+// Listens for heartbeats from the primary until they stop, then returns the last state it sent.
+// Returns None if the primary died before the elevator had a state.
+pub fn wait_as_backup(pair_port: u16) -> std::io::Result<Option<PairState>> {
+    let socket = UdpSocket::bind(pair_addr(pair_port))?;
+    socket.set_read_timeout(Some(PAIR_TIMEOUT))?;
+    println!("Running as backup, waiting for the primary to stop");
+
+    let mut last_state: Option<PairState> = None;
+    let mut buf = [0; 4096];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((size, _)) => {
+                if let Ok(state) = serde_json::from_slice::<Option<PairState>>(&buf[..size]) {
+                    if state.is_some() {
+                        last_state = state;
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => {
+                println!("Primary stopped sending heartbeats, taking over");
+                return Ok(last_state);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+}
+
+// Starts a backup of this program with the same arguments. The backup gets its own process group,
+// so a Ctrl-C in the terminal only kills the primary.
+pub fn spawn_backup(args: &[String]) -> std::io::Result<Child> {
+    let backup_args: Vec<&String> = args.iter().skip(1).filter(|a| *a != "--backup").collect();
+    let child = Command::new(std::env::current_exe()?)
+        .args(backup_args)
+        .arg("--backup")
+        .process_group(0)
+        .spawn()?;
+    println!("Spawned backup with pid {}", child.id());
+    return Ok(child);
+}
+
+// Sends the latest elevator state to the backup for as long as the elevator runs.
+// Returns once the elevator thread is gone, so the heartbeat stops and the backup takes over.
+pub fn pair_heartbeat_sender(pair_port: u16, pair_state_rx: Receiver<PairState>) {
+    let mut last_state: Option<PairState> = None;
+    loop {
+        if let Ok(socket) = UdpSocket::bind("127.0.0.1:0") {
+            loop {
+                // Only the newest state matters
+                loop {
+                    match pair_state_rx.try_recv() {
+                        Ok(state) => last_state = Some(state),
+                        Err(cbc::TryRecvError::Empty) => break,
+                        Err(cbc::TryRecvError::Disconnected) => return,
+                    }
+                }
+                let json = serde_json::to_string(&last_state).unwrap();
+                if let Err(e) = socket.send_to(json.as_bytes(), pair_addr(pair_port)) {
+                    eprintln!("Heartbeat send error: {}", e);
+                    break;
+                }
+                sleep(PAIR_HEARTBEAT_PERIOD);
+            }
+        }
+        sleep(Duration::from_secs(1));
+    }
+}
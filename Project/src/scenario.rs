@@ -0,0 +1,437 @@
+use crate::prelude::*;
+use crate::config::Config;
+use crate::network::server::NetworkUnit;
+use crate::network::transport::*;
+use crate::client::simulator::*;
+use crate::client::utils::direction_to_string;
+use crate::node::start_node;
+use crate::client::assigner::AssignerKind;
+use crate::clock::*;
+
+// How far the runner moves the clock at a time, and the real time the units get after each step to catch up
+const STEP: Duration = Duration::from_millis(5);
+const STEP_PAUSE: Duration = Duration::from_micros(200);
+// How often the runner looks at the elevators while waiting for them
+const CHECK_PERIOD: Duration = Duration::from_millis(100);
+// Longer than any unit thread waits before it looks at its stopped flag again
+const SHUTDOWN_TIME: Duration = Duration::from_secs(6);
+// Time the elevators get to find a floor after starting, before the timeline starts
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(20);
+// Hall lights wait for the master to confirm the order. A call served sooner than this may never light up.
+const LIGHT_GRACE: Duration = Duration::from_secs(2);
+
+// Something that happens during a scenario
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ScenarioEvent {
+    Press { node: u8, floor: u8, call: CallKind },
+    Kill { node: u8 }, // The unit crashes and its elevator loses power
+    Restart { node: u8 }, // A killed unit starts again, with its cab journal
+    Obstruction { node: u8, on: bool },
+    StopButton { node: u8, on: bool },
+    MotorFailure { node: u8, on: bool }, // The car does not move while on
+    Partition { groups: Vec<Vec<u8>> },
+    Heal,
+    Faults { faults: Faults },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimedEvent {
+    pub at_ms: u64, // Time after the elevators have settled
+    #[serde(flatten)]
+    pub event: ScenarioEvent,
+}
+
+// Invariants a scenario can check
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    Served, // Every call is eventually served by opening the door at its floor
+    Lights, // Every call lights its button, and no lights are left on at the end
+    Door, // The door never opens while the car is moving or between floors
+    OneMaster, // Once everything is served, the units that are alive agree on a single master
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct NodeSetup {
+    pub id: u8,
+    #[serde(default)]
+    pub start_floor: u8,
+}
+
+// A scripted run of a cluster of units on simulated hardware and a simulated network.
+// Loaded from a JSON file, see the files in tests/scenarios.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub name: String,
+    pub floors: u8,
+    pub nodes: Vec<NodeSetup>, // Started one at a time, in this order
+    pub travel_time_ms: u64, // Time to move one floor
+    pub door_open_ms: u64,
+    pub motor_timeout_ms: u64,
+    pub heartbeat_timeout_ms: u64,
+    pub startup_wait_ms: u64,
+    pub timeout_ms: u64, // Time after the last event the calls have to be served within
+    pub seed: u64, // Seed for the network faults
+    pub faults: Faults,
+    pub assigner: AssignerKind,
+    pub checks: Vec<Check>,
+    pub events: Vec<TimedEvent>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            name: String::from("scenario"),
+            floors: 4,
+            nodes: Vec::new(),
+            travel_time_ms: 500,
+            door_open_ms: 500,
+            motor_timeout_ms: 2000,
+            heartbeat_timeout_ms: 1000,
+            startup_wait_ms: 2000,
+            timeout_ms: 30000,
+            seed: 1,
+            faults: Faults::default(),
+            assigner: AssignerKind::TimeToIdle,
+            checks: vec![Check::Served, Check::Lights, Check::Door],
+            events: Vec::new(),
+        }
+    }
+}
+
+// How long the calls of a finished scenario waited, from the button press until a door opened for them
+#[derive(Clone, Copy, Debug)]
+pub struct ScenarioReport {
+    pub calls: usize,
+    pub average_wait: Duration,
+    pub longest_wait: Duration,
+}
+
+// A button press made during the run, kept for checking afterwards
+#[derive(Clone, Debug)]
+struct Press {
+    at: Instant,
+    node: u8,
+    floor: u8,
+    call: CallKind,
+    alive: Vec<u8>, // The units that were running when the button was pressed
+}
+
+impl Scenario {
+    pub fn load(path: &std::path::Path) -> anyhow::Result<Scenario> {
+        let text = std::fs::read_to_string(path)?;
+        let scenario: Scenario = serde_json::from_str(&text)?;
+        return Ok(scenario);
+    }
+
+    fn journal_path(&self, id: u8) -> String {
+        let name: String = self.name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let file = format!("scenario_{}_{}_{}.json", name, std::process::id(), id);
+        return std::env::temp_dir().join(file).to_string_lossy().into_owned();
+    }
+
+    fn config(&self, id: u8) -> Config {
+        Config {
+            id,
+            num_floors: self.floors,
+            heartbeat_timeout_ms: self.heartbeat_timeout_ms,
+            startup_wait_ms: self.startup_wait_ms,
+            door_open_ms: self.door_open_ms,
+            travel_time_ms: self.travel_time_ms,
+            motor_timeout_ms: self.motor_timeout_ms,
+            assigner: self.assigner,
+            cab_journal: Some(self.journal_path(id)),
+            ..Config::default()
+        }
+    }
+
+    // Connects a unit to the bus
+    fn connect(&self, bus: &MemoryBus, clock: &ManualClock, id: u8) -> NetworkUnit {
+        return NetworkUnit::with_transport(&self.config(id), Arc::new(bus.connect(id)), Arc::new(clock.clone()));
+    }
+
+    // Starts a unit. Returns once it has picked its role.
+    fn start(&self, network_unit: NetworkUnit, elevator: SimulatedElevator) -> () {
+        start_node(&self.config(network_unit.id), network_unit, elevator, None, None);
+    }
+
+    // Runs the scenario and checks its invariants. The error lists every violation.
+    // The units run on a manual clock that the runner moves forward, so a scenario takes far less than its own time.
+    // Every unit is stopped afterwards, so nothing is left running when the next scenario starts.
+    pub fn run(&self) -> anyhow::Result<ScenarioReport> {
+        let clock = ManualClock::new();
+        let mut network_units: HashMap<u8, NetworkUnit> = HashMap::new();
+        let result = self.play(&clock, &mut network_units);
+        for network_unit in network_units.values() {
+            network_unit.stop();
+        }
+        // The threads only see that they are stopped once their waits are over
+        advance(&clock, SHUTDOWN_TIME);
+        return result;
+    }
+
+    fn play(&self, clock: &ManualClock, network_units: &mut HashMap<u8, NetworkUnit>) -> anyhow::Result<ScenarioReport> {
+        let bus = MemoryBus::with_clock(self.seed, Arc::new(clock.clone()));
+        bus.set_faults(self.faults);
+        let travel_time = Duration::from_millis(self.travel_time_ms);
+
+        // Boot the units one at a time, so each one sees the roles taken before it
+        let mut elevators: HashMap<u8, SimulatedElevator> = HashMap::new();
+        let mut alive: HashSet<u8> = HashSet::new();
+        for node in &self.nodes {
+            let _ = std::fs::remove_file(self.journal_path(node.id));
+            let elevator = SimulatedElevator::with_clock(self.floors, travel_time, node.start_floor, Arc::new(clock.clone()));
+            let network_unit = self.connect(&bus, clock, node.id);
+            // The unit listens for the others for a while before it picks its role, and that takes clock time
+            let starting = {
+                let scenario = self.clone();
+                let network_unit = network_unit.clone();
+                let elevator = elevator.clone();
+                spawn(move || scenario.start(network_unit, elevator))
+            };
+            while !starting.is_finished() {
+                step(clock);
+            }
+            network_units.insert(node.id, network_unit);
+            elevators.insert(node.id, elevator);
+            alive.insert(node.id);
+        }
+        if !wait_until(clock, SETTLE_TIMEOUT, || elevators.values().all(is_idle)) {
+            return Err(anyhow!("{}: the elevators never stopped at a floor after starting", self.name));
+        }
+        // The timeline starts once every unit has heard from all the others
+        if !wait_until(clock, SETTLE_TIMEOUT, || network_units.values().all(|u| u.get_alive_states().len() == self.nodes.len())) {
+            return Err(anyhow!("{}: the units never all saw each other after starting", self.name));
+        }
+
+        // Play the timeline
+        let start = clock.now();
+        let mut presses: Vec<Press> = Vec::new();
+        let mut events = self.events.clone();
+        events.sort_by_key(|e| e.at_ms);
+        for timed in events {
+            let at = start + Duration::from_millis(timed.at_ms);
+            advance(clock, at.saturating_duration_since(clock.now()));
+            println!("Scenario {}: {:?}", self.name, timed.event);
+            let elevator = |node: u8| elevators.get(&node).ok_or_else(|| anyhow!("{}: no node {}", self.name, node));
+            match timed.event {
+                ScenarioEvent::Press { node, floor, call } => {
+                    elevator(node)?.press_button(floor, call.button());
+                    presses.push(Press { at: clock.now(), node, floor, call, alive: alive.iter().copied().collect() });
+                }
+                ScenarioEvent::Kill { node } => {
+                    if let Some(network_unit) = network_units.get(&node) {
+                        network_unit.stop();
+                    }
+                    bus.disconnect(node);
+                    elevator(node)?.power_off();
+                    alive.remove(&node);
+                }
+                ScenarioEvent::Restart { node } => {
+                    let handle = elevator(node)?.power_on();
+                    let network_unit = self.connect(&bus, clock, node);
+                    network_units.insert(node, network_unit.clone());
+                    let scenario = self.clone();
+                    spawn(move || scenario.start(network_unit, handle));
+                    alive.insert(node);
+                }
+                ScenarioEvent::Obstruction { node, on } => elevator(node)?.set_obstruction(on),
+                ScenarioEvent::StopButton { node, on } => elevator(node)?.set_stop_button(on),
+                ScenarioEvent::MotorFailure { node, on } => elevator(node)?.set_motor_failure(on),
+                ScenarioEvent::Partition { groups } => bus.partition(groups),
+                ScenarioEvent::Heal => bus.heal(),
+                ScenarioEvent::Faults { faults } => bus.set_faults(faults),
+            }
+        }
+
+        // Wait for every call to be served, and then for the elevators to finish their door cycles
+        let timeout = Duration::from_millis(self.timeout_ms);
+        let served_in_time = wait_until(clock, timeout, || unserved(&presses, &elevators).is_empty());
+        let idle_time = Duration::from_millis(2 * self.door_open_ms) + CHECK_PERIOD;
+        if served_in_time {
+            wait_until(clock, timeout, || {
+                let idle = alive.iter().all(|id| is_idle(&elevators[id]));
+                if idle {
+                    advance(clock, idle_time);
+                }
+                return idle && alive.iter().all(|id| is_idle(&elevators[id]));
+            });
+        }
+
+        let mut failures: Vec<String> = Vec::new();
+        if self.checks.contains(&Check::Served) {
+            for press in unserved(&presses, &elevators) {
+                failures.push(format!("{:?} at floor {} pressed on node {} was never served", press.call, press.floor, press.node));
+            }
+        }
+        if self.checks.contains(&Check::Lights) {
+            failures.extend(check_lights(&presses, &elevators, &alive, self.floors));
+        }
+        if self.checks.contains(&Check::Door) {
+            for (id, elevator) in &elevators {
+                failures.extend(check_door(*id, elevator));
+            }
+        }
+        if self.checks.contains(&Check::OneMaster) {
+            let masters = || -> Vec<u8> {
+                let mut masters: Vec<u8> = alive.iter()
+                    .filter(|id| network_units[*id].get_role() == Role::Master)
+                    .copied()
+                    .collect();
+                masters.sort();
+                return masters;
+            };
+            if !wait_until(clock, timeout, || masters().len() == 1) {
+                failures.push(format!("The units that are alive have masters {:?}, not one", masters()));
+            }
+        }
+
+        for node in &self.nodes {
+            let _ = std::fs::remove_file(self.journal_path(node.id));
+        }
+        if !failures.is_empty() {
+            return Err(anyhow!("{} failed:\n{}", self.name, failures.join("\n")));
+        }
+
+        let waits: Vec<Duration> = presses.iter()
+            .filter_map(|p| wait_time(p, &elevators))
+            .collect();
+        let report = ScenarioReport {
+            calls: waits.len(),
+            average_wait: waits.iter().sum::<Duration>() / max(waits.len(), 1) as u32,
+            longest_wait: waits.iter().max().copied().unwrap_or_default(),
+        };
+        println!("Scenario {} with {:?}: {:?}", self.name, self.assigner, report);
+        return Ok(report);
+    }
+}
+
+// Moves the clock one step, and gives the units a moment to do what is due
+fn step(clock: &ManualClock) {
+    clock.advance(STEP);
+    sleep(STEP_PAUSE);
+}
+
+fn advance(clock: &ManualClock, duration: Duration) {
+    let until = clock.now() + duration;
+    while clock.now() < until {
+        step(clock);
+    }
+}
+
+// Polls the condition until it holds or the timeout runs out on the clock. Returns whether it held.
+fn wait_until<F: FnMut() -> bool>(clock: &ManualClock, timeout: Duration, mut condition: F) -> bool {
+    let deadline = clock.now() + timeout;
+    loop {
+        if condition() {
+            return true;
+        }
+        if clock.now() >= deadline {
+            return false;
+        }
+        advance(clock, CHECK_PERIOD);
+    }
+}
+
+fn is_idle(elevator: &SimulatedElevator) -> bool {
+    elevator.motor() == e::DIRN_STOP && !elevator.door_open() && elevator.floor().is_some()
+}
+
+// Time from the call until an elevator first opened its door at the floor, if one has.
+// Cab calls have to be served by their own elevator, hall calls by any of them.
+fn wait_time(press: &Press, elevators: &HashMap<u8, SimulatedElevator>) -> Option<Duration> {
+    let opened_at_floor = |elevator: &SimulatedElevator| {
+        elevator.history().iter()
+            .find(|(at, event)| *at >= press.at && *event == HardwareEvent::Door { open: true, floor: Some(press.floor) })
+            .map(|(at, _)| at.duration_since(press.at))
+    };
+    if press.call == CallKind::Cab {
+        return opened_at_floor(&elevators[&press.node]);
+    }
+    return elevators.values().filter_map(opened_at_floor).min();
+}
+
+fn is_served(press: &Press, elevators: &HashMap<u8, SimulatedElevator>) -> bool {
+    wait_time(press, elevators).is_some()
+}
+
+fn unserved(presses: &[Press], elevators: &HashMap<u8, SimulatedElevator>) -> Vec<Press> {
+    presses.iter()
+        .filter(|p| !is_served(p, elevators))
+        .cloned()
+        .collect()
+}
+
+// Every button lights up when pressed, and once everything is served no lights are left on.
+// A cab call lights up on its own panel, a hall call on the panel of every unit that was running when it was
+// pressed and still is. Hall calls served within LIGHT_GRACE don't have to light up.
+fn check_lights(presses: &[Press], elevators: &HashMap<u8, SimulatedElevator>, alive: &HashSet<u8>, floors: u8) -> Vec<String> {
+    let mut failures = Vec::new();
+    for press in presses {
+        let served_at_once = press.call != CallKind::Cab && wait_time(press, elevators).is_some_and(|wait| wait < LIGHT_GRACE);
+        if served_at_once {
+            continue;
+        }
+        let mut panels: Vec<u8> = match press.call {
+            CallKind::Cab => vec![press.node],
+            _ => press.alive.iter()
+                .filter(|id| alive.contains(id) && !lost_power_since(&elevators[*id], press.at))
+                .copied()
+                .collect(),
+        };
+        panels.sort();
+        for id in panels {
+            let lit = elevators[&id].history().iter().any(|(at, event)| {
+                *at >= press.at && *event == HardwareEvent::Light { floor: press.floor, call: press.call.button(), on: true }
+            });
+            if !lit {
+                failures.push(format!("{:?} at floor {} pressed on node {} never lit its light on node {}", press.call, press.floor, press.node, id));
+            }
+        }
+    }
+    for id in alive {
+        for floor in 0..floors {
+            for call in [CallKind::HallUp, CallKind::HallDown, CallKind::Cab] {
+                if elevators[id].call_light(floor, call.button()) {
+                    failures.push(format!("{:?} light at floor {} on node {} is still on after all calls were served", call, floor, id));
+                }
+            }
+        }
+    }
+    return failures;
+}
+
+fn lost_power_since(elevator: &SimulatedElevator, since: Instant) -> bool {
+    elevator.history().iter().any(|(at, event)| *at >= since && *event == HardwareEvent::PowerOff)
+}
+
+// The door may only be open while the car stands still at a floor
+fn check_door(id: u8, elevator: &SimulatedElevator) -> Vec<String> {
+    let mut failures = Vec::new();
+    let mut motor = e::DIRN_STOP;
+    let mut door_open = false;
+    for (_, event) in elevator.history() {
+        match event {
+            HardwareEvent::Motor(dirn) => motor = dirn,
+            HardwareEvent::Door { open, floor } => {
+                door_open = open;
+                if open && floor.is_none() {
+                    failures.push(format!("Node {} opened its door between floors", id));
+                }
+            }
+            HardwareEvent::PowerOff => {
+                motor = e::DIRN_STOP;
+                door_open = false;
+            }
+            HardwareEvent::Light { .. } => {}
+        }
+        if door_open && motor != e::DIRN_STOP {
+            failures.push(format!("Node {} had its door open while the motor was running ({})", id, direction_to_string(motor)));
+        }
+    }
+    return failures;
+}
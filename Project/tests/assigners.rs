@@ -0,0 +1,21 @@
+use std::path::Path;
+use ttk4145_project::scenario::Scenario;
+use ttk4145_project::client::assigner::AssignerKind;
+
+// Plays the same traffic with every assignment strategy, one after another, and prints how long the calls waited.
+// Takes a while, so run it with: cargo test --test assigners -- --ignored --nocapture
+#[test]
+#[ignore]
+fn compare_assigners() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("benchmarks").join("peak_traffic.json");
+    let traffic = Scenario::load(&path).unwrap();
+
+    let kinds = [AssignerKind::TimeToIdle, AssignerKind::NearestCar, AssignerKind::RoundRobin, AssignerKind::Zoning];
+    for kind in kinds {
+        let mut scenario = traffic.clone();
+        scenario.assigner = kind;
+        scenario.name = format!("{} {:?}", traffic.name, kind);
+        let report = scenario.run().unwrap();
+        println!("{:?}: {} calls, average wait {:?}, longest wait {:?}", kind, report.calls, report.average_wait, report.longest_wait);
+    }
+}
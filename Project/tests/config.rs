@@ -0,0 +1,15 @@
+use ttk4145_project::config::Config;
+
+fn args(list: &[&str]) -> Vec<String> {
+    return std::iter::once("elevator").chain(list.iter().copied()).map(String::from).collect();
+}
+
+// The floors have to fit in the destination bit masks, and there has to be at least one
+#[test]
+fn floor_count_is_checked() {
+    assert!(Config::from_args(&args(&["--floors", "0"])).is_err());
+    assert!(Config::from_args(&args(&["--floors", "17"])).is_err());
+    assert_eq!(Config::from_args(&args(&["--floors", "1"])).unwrap().num_floors, 1);
+    assert_eq!(Config::from_args(&args(&["--floors", "16"])).unwrap().num_floors, 16);
+    assert_eq!(Config::from_args(&args(&[])).unwrap().num_floors, 4);
+}
@@ -0,0 +1,115 @@
+use ttk4145_project::prelude::*;
+use ttk4145_project::client::cost::*;
+use ttk4145_project::client::assigner::Units;
+use std::collections::BTreeSet;
+
+const TIMING: CostTiming = CostTiming { travel_time: Duration::from_secs(2), door_open_time: Duration::from_secs(3) };
+
+fn order(floor_number: u8, call: CallKind) -> Order {
+    return Order { floor_number, call };
+}
+
+fn orders(list: &[Order]) -> HashSet<Order> {
+    return list.iter().copied().collect();
+}
+
+fn status(last_floor: u8, direction: u8) -> Status {
+    return Status { last_floor, direction, ..Status::new() };
+}
+
+// An idle car drives straight to the call and opens the door once
+#[test]
+fn idle_car() {
+    let idle = status(0, e::DIRN_STOP);
+    assert_eq!(time_to_idle(&idle, HashSet::new(), TIMING), Duration::ZERO);
+    assert_eq!(time_to_idle(&idle, orders(&[order(2, CallKind::HallUp)]), TIMING), Duration::from_secs(7));
+
+    // Standing at the floor, both calls there are served with one opening
+    let mut here = orders(&[order(0, CallKind::HallUp), order(0, CallKind::HallDown)]);
+    assert!(should_stop(&here, 0, e::DIRN_STOP));
+    clear_at_floor(&mut here, 0, e::DIRN_STOP);
+    assert!(here.is_empty());
+}
+
+// A call in the direction the car is going is served on the way, for the cost of one more stop
+#[test]
+fn car_serving_an_order_on_its_way() {
+    let moving = status(0, e::DIRN_UP);
+    let cab_at_3 = order(3, CallKind::Cab);
+    assert_eq!(time_to_idle(&moving, orders(&[cab_at_3]), TIMING), Duration::from_secs(8));
+    assert_eq!(time_to_idle(&moving, orders(&[cab_at_3, order(1, CallKind::HallUp)]), TIMING), Duration::from_secs(11));
+
+    // Cab orders and calls our way stop the car, calls the other way don't while there is more ahead
+    assert!(should_stop(&orders(&[cab_at_3, order(1, CallKind::HallUp)]), 1, e::DIRN_UP));
+    assert!(should_stop(&orders(&[cab_at_3, order(1, CallKind::Cab)]), 1, e::DIRN_UP));
+    assert!(!should_stop(&orders(&[cab_at_3, order(1, CallKind::HallDown)]), 1, e::DIRN_UP));
+
+    // Stopping only serves the call our way
+    let mut destinations = orders(&[cab_at_3, order(1, CallKind::HallUp), order(1, CallKind::HallDown)]);
+    clear_at_floor(&mut destinations, 1, e::DIRN_UP);
+    assert_eq!(destinations, orders(&[cab_at_3, order(1, CallKind::HallDown)]));
+}
+
+// A call the other way is passed, and served after the car has been to the end of its route and turned around
+#[test]
+fn car_that_must_turn_around_first() {
+    let moving = status(1, e::DIRN_UP);
+    let destinations = orders(&[order(3, CallKind::Cab), order(2, CallKind::HallDown)]);
+    assert!(!should_stop(&destinations, 2, e::DIRN_UP));
+    // Half a floor to 2, on to 3 and open, back to 2 and open
+    assert_eq!(time_to_idle(&moving, destinations, TIMING), Duration::from_secs(11));
+
+    // With nothing more ahead the car stops and serves both directions, as it turns here
+    let mut at_top = orders(&[order(3, CallKind::HallDown), order(3, CallKind::Cab), order(0, CallKind::Cab)]);
+    assert!(should_stop(&at_top, 3, e::DIRN_UP));
+    clear_at_floor(&mut at_top, 3, e::DIRN_UP);
+    assert_eq!(at_top, orders(&[order(0, CallKind::Cab)]));
+}
+
+// Three units spread over the shaft, each with something to do already
+fn busy_units(order_of_insertion: &[u8]) -> Units {
+    let mut units = Units::new();
+    for id in order_of_insertion {
+        let (unit, destinations) = match id {
+            1 => (status(0, e::DIRN_UP), vec![order(3, CallKind::Cab), order(1, CallKind::HallUp)]),
+            2 => (status(3, e::DIRN_STOP), vec![order(0, CallKind::Cab)]),
+            _ => (status(2, e::DIRN_DOWN), vec![order(1, CallKind::HallDown), order(0, CallKind::Cab), order(3, CallKind::HallDown)]),
+        };
+        // Build the destinations in the order of insertion too, so their hash set iterates differently
+        let destinations = if order_of_insertion[0] == 1 { destinations } else { destinations.into_iter().rev().collect() };
+        units.insert(*id, (unit, orders(&destinations)));
+    }
+    return units;
+}
+
+fn new_orders() -> BTreeSet<Order> {
+    return [order(0, CallKind::HallUp), order(2, CallKind::HallUp), order(2, CallKind::HallDown), order(3, CallKind::HallDown), order(1, CallKind::HallDown)]
+        .into_iter().collect();
+}
+
+// The master may be replaced at any time, and the new one has to come up with the same assignment
+#[test]
+fn assignment_is_deterministic() {
+    let first = assign_orders(&new_orders(), &busy_units(&[1, 2, 3]), TIMING);
+    assert_eq!(first.len(), new_orders().len());
+    assert_eq!(assign_orders(&new_orders(), &busy_units(&[1, 2, 3]), TIMING), first);
+    assert_eq!(assign_orders(&new_orders(), &busy_units(&[3, 1, 2]), TIMING), first);
+    assert_eq!(assign_orders(&new_orders(), &busy_units(&[2, 3, 1]), TIMING), first);
+}
+
+// Between units that would take equally long, the one with fewer destinations wins, and then the lowest id
+#[test]
+fn equal_cost_ties_are_broken() {
+    let hall_up_at_2: BTreeSet<Order> = [order(2, CallKind::HallUp)].into_iter().collect();
+
+    // Unit 1 has to open its door where it is first, which takes as long as unit 2 spends in total
+    let mut units = Units::new();
+    units.insert(1, (status(0, e::DIRN_STOP), orders(&[order(0, CallKind::Cab)])));
+    units.insert(2, (status(0, e::DIRN_STOP), HashSet::new()));
+    assert_eq!(assign_orders(&hall_up_at_2, &units, TIMING)[&order(2, CallKind::HallUp)], 2);
+
+    let mut units = Units::new();
+    units.insert(5, (status(0, e::DIRN_STOP), HashSet::new()));
+    units.insert(3, (status(0, e::DIRN_STOP), HashSet::new()));
+    assert_eq!(assign_orders(&hall_up_at_2, &units, TIMING)[&order(2, CallKind::HallUp)], 3);
+}
@@ -0,0 +1,161 @@
+use ttk4145_project::prelude::*;
+use ttk4145_project::client::elevator::*;
+use ttk4145_project::client::hardware::ElevatorHardware;
+use ttk4145_project::client::simulator::SimulatedElevator;
+use ttk4145_project::clock::SystemClock;
+
+// The simulated shaft behind a connection that can be lost. Like the real driver with a poisoned lock,
+// every call panics once it is broken, until we connect again while the hardware server is up.
+#[derive(Clone)]
+struct FlakyElevator {
+    shaft: SimulatedElevator,
+    broken: Arc<atomic::AtomicBool>,
+    server_up: Arc<atomic::AtomicBool>,
+}
+
+impl FlakyElevator {
+    fn check(&self) {
+        if self.broken.load(atomic::Ordering::SeqCst) {
+            panic!("Lost the connection to the hardware server");
+        }
+    }
+}
+
+impl ElevatorHardware for FlakyElevator {
+    fn num_floors(&self) -> u8 {
+        self.shaft.num_floors()
+    }
+    fn motor_direction(&self, dirn: u8) {
+        self.check();
+        self.shaft.motor_direction(dirn)
+    }
+    fn call_button_light(&self, floor: u8, call: u8, on: bool) {
+        self.check();
+        self.shaft.call_button_light(floor, call, on)
+    }
+    fn floor_indicator(&self, floor: u8) {
+        self.check();
+        self.shaft.floor_indicator(floor)
+    }
+    fn door_light(&self, on: bool) {
+        self.check();
+        self.shaft.door_light(on)
+    }
+    fn stop_button_light(&self, on: bool) {
+        self.check();
+        self.shaft.stop_button_light(on)
+    }
+    fn call_button(&self, floor: u8, call: u8) -> bool {
+        self.check();
+        self.shaft.call_button(floor, call)
+    }
+    fn floor_sensor(&self) -> Option<u8> {
+        self.check();
+        self.shaft.floor_sensor()
+    }
+    fn stop_button(&self) -> bool {
+        self.check();
+        self.shaft.stop_button()
+    }
+    fn obstruction(&self) -> bool {
+        self.check();
+        self.shaft.obstruction()
+    }
+    fn reconnect(&self) -> bool {
+        if !self.server_up.load(atomic::Ordering::SeqCst) {
+            return false;
+        }
+        self.broken.store(false, atomic::Ordering::SeqCst);
+        return true;
+    }
+}
+
+// Waits for a status from the elevator that passes the check
+fn wait_for_status(comms_rx: &Receiver<Communication>, timeout: Duration, check: impl Fn(&Status) -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while let Ok(message) = comms_rx.recv_deadline(deadline) {
+        if message.comm_type == MessageKind::Status && check(&message.status.unwrap()) {
+            return true;
+        }
+    }
+    return false;
+}
+
+fn wait_until(timeout: Duration, check: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if check() {
+            return true;
+        }
+        sleep(Duration::from_millis(25));
+    }
+    return false;
+}
+
+fn hall_lights_message(hall_lights: Destinations) -> Communication {
+    return Communication {
+        sender: 0,
+        sender_role: Some(Role::Master),
+        target: Target::All,
+        comm_type: MessageKind::HallLights,
+        status: None,
+        order: None,
+        hall_lights: Some(hall_lights),
+    };
+}
+
+// Losing the hardware, writes included, shows up as a driver failure instead of killing the elevator thread,
+// and once the hardware server is back the elevator connects again and carries on
+#[test]
+fn elevator_survives_lost_hardware() {
+    let shaft = SimulatedElevator::new(4, Duration::from_millis(100), 0);
+    let elevator = FlakyElevator {
+        shaft: shaft.clone(),
+        broken: Arc::new(atomic::AtomicBool::new(false)),
+        server_up: Arc::new(atomic::AtomicBool::new(true)),
+    };
+    let journal = std::env::temp_dir().join(format!("driver_failure_{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&journal);
+    let stopped = Arc::new(atomic::AtomicBool::new(false));
+
+    let (out_tx, out_rx) = cbc::unbounded::<Communication>();
+    let (in_tx, in_rx) = cbc::unbounded::<Communication>();
+    let elevator_thread = {
+        let elevator = elevator.clone();
+        let settings = ElevatorSettings {
+            id: 1,
+            num_floors: 4,
+            poll_period: Duration::from_millis(25),
+            door_open_time: Duration::from_millis(300),
+            motor_timeout: Duration::from_secs(10),
+            cab_journal: journal.to_string_lossy().into_owned(),
+            clock: Arc::new(SystemClock),
+        };
+        let channels = ElevatorChannels { comms_channel_tx: out_tx, comms_channel_rx: in_rx, pair_state_tx: None };
+        let stopped = stopped.clone();
+        spawn(move || run_elevator(elevator, settings, channels, None, stopped))
+    };
+    assert!(wait_for_status(&out_rx, Duration::from_secs(3), |status| status.last_floor == 0 && status.error.is_none()));
+
+    // The hardware server goes away, and the master asks for a hall light while it is gone
+    elevator.server_up.store(false, atomic::Ordering::SeqCst);
+    elevator.broken.store(true, atomic::Ordering::SeqCst);
+    let up_at_2 = Destinations { up: 1 << 2, down: 0, cab: 0 };
+    in_tx.send(hall_lights_message(up_at_2)).unwrap();
+    assert!(wait_for_status(&out_rx, Duration::from_secs(3), |status| status.error == Some(ElevatorError::DriverFailure)));
+    assert!(!elevator_thread.is_finished());
+
+    // Once it is back the fault clears, and the light it missed is written
+    elevator.server_up.store(true, atomic::Ordering::SeqCst);
+    assert!(wait_for_status(&out_rx, Duration::from_secs(3), |status| status.error.is_none()));
+    assert!(wait_until(Duration::from_secs(1), || shaft.call_light(2, e::HALL_UP)));
+
+    // The motor and door can be driven again
+    shaft.press_button(3, e::CAB);
+    assert!(wait_until(Duration::from_secs(3), || shaft.floor() == Some(3) && shaft.door_open()));
+
+    stopped.store(true, atomic::Ordering::SeqCst);
+    drop(in_tx);
+    elevator_thread.join().unwrap();
+    let _ = std::fs::remove_file(&journal);
+}
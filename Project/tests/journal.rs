@@ -0,0 +1,32 @@
+use ttk4145_project::prelude::*;
+use ttk4145_project::client::journal::*;
+
+fn journal_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("{}_{}.json", name, std::process::id()));
+    return path.to_string_lossy().into_owned();
+}
+
+#[test]
+fn cab_orders_survive_a_restart() {
+    let path = journal_path("journal_round_trip");
+    let cab_orders = HashSet::from([
+        Order { floor_number: 0, call: CallKind::Cab },
+        Order { floor_number: 3, call: CallKind::Cab },
+    ]);
+    save_cab_orders(&path, &cab_orders).unwrap();
+    assert_eq!(load_cab_orders(&path), cab_orders);
+    std::fs::remove_file(&path).unwrap();
+}
+
+// Journals written before orders had a CallKind saved a direction, which was not the cab button
+#[test]
+fn old_journal_gives_cab_orders() {
+    let path = journal_path("journal_old_format");
+    std::fs::write(&path, r#"[{"floor_number":1,"direction":0},{"floor_number":2,"direction":2}]"#).unwrap();
+    let expected = HashSet::from([
+        Order { floor_number: 1, call: CallKind::Cab },
+        Order { floor_number: 2, call: CallKind::Cab },
+    ]);
+    assert_eq!(load_cab_orders(&path), expected);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,127 @@
+use ttk4145_project::prelude::*;
+use ttk4145_project::config::Config;
+use ttk4145_project::network::server::*;
+use ttk4145_project::network::transport::*;
+use ttk4145_project::clock::SystemClock;
+
+// A unit with only its network threads running
+struct TestUnit {
+    network_unit: NetworkUnit,
+    elevator_rx: Receiver<Communication>, // What the unit would hand to its elevator
+    _network_channel_tx: Sender<Communication>, // Kept open, the sender stops once it closes
+}
+
+fn start_unit(bus: &MemoryBus, id: u8) -> TestUnit {
+    let config = Config { id, ..Config::default() };
+    let network_unit = NetworkUnit::with_transport(&config, Arc::new(bus.connect(id)), Arc::new(SystemClock));
+    let (network_channel_tx, network_channel_rx) = cbc::unbounded::<Communication>();
+    let (master_tx, _) = cbc::unbounded::<Communication>();
+    let (backup_tx, _) = cbc::unbounded::<Communication>();
+    let (elevator_tx, elevator_rx) = cbc::unbounded::<Communication>();
+    {
+        let network_unit = network_unit.clone();
+        spawn(move || network_periodic_sender(network_unit, network_channel_rx));
+    }
+    {
+        let network_unit = network_unit.clone();
+        spawn(move || network_receiver(network_unit, master_tx, backup_tx, elevator_tx));
+    }
+    return TestUnit { network_unit, elevator_rx, _network_channel_tx: network_channel_tx };
+}
+
+fn order_to(id: u8, floor: u8) -> Communication {
+    return Communication {
+        sender: u8::MAX,
+        sender_role: None,
+        target: Target::Unit(id),
+        comm_type: MessageKind::OrderTransfer,
+        status: None,
+        order: Some(Order { floor_number: floor, call: CallKind::Cab }),
+        hall_lights: None,
+    };
+}
+
+// Every message gets through exactly once and is reported delivered, though packets and acknowledgements
+// are lost, duplicated and reordered on the way
+#[test]
+fn lossy_network_delivers_each_message_once() {
+    let bus = MemoryBus::new(7);
+    bus.set_faults(Faults { loss: 0.2, duplication: 0.5, reorder: 0.2, delay_ms: 1, reorder_delay_ms: 20 });
+    let sender = start_unit(&bus, 1);
+    let receiver = start_unit(&bus, 2);
+
+    let (report_tx, report_rx) = cbc::unbounded::<DeliveryReport>();
+    for floor in 0..20 {
+        sender.network_unit.send_reliable(order_to(2, floor), &report_tx);
+    }
+    for _ in 0..20 {
+        let (message, delivered) = report_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(delivered, "{:?} was not delivered", message.order);
+    }
+
+    // Resent and duplicated copies are dropped by their sequence numbers
+    let mut floors = Vec::new();
+    while let Ok(message) = receiver.elevator_rx.recv_timeout(Duration::from_secs(1)) {
+        floors.push(message.order.unwrap().floor_number);
+    }
+    floors.sort();
+    assert_eq!(floors, (0..20).collect::<Vec<u8>>());
+
+    sender.network_unit.stop();
+    receiver.network_unit.stop();
+}
+
+// The delivery report comes as soon as the acknowledgement is in, not when the sender next wakes up to resend
+#[test]
+fn delivery_is_reported_right_away() {
+    let bus = MemoryBus::new(1);
+    let sender = start_unit(&bus, 1);
+    let receiver = start_unit(&bus, 2);
+
+    let (report_tx, report_rx) = cbc::unbounded::<DeliveryReport>();
+    let sent_at = Instant::now();
+    sender.network_unit.send_reliable(order_to(2, 1), &report_tx);
+    let (_, delivered) = report_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(delivered);
+    assert!(sent_at.elapsed() < Duration::from_millis(50), "Reported after {:?}", sent_at.elapsed());
+
+    sender.network_unit.stop();
+    receiver.network_unit.stop();
+}
+
+// A message nobody acknowledges is resent with a doubling wait, and reported failed after the last attempt
+#[test]
+fn unacknowledged_message_is_reported_failed() {
+    let bus = MemoryBus::new(1);
+    let sender = start_unit(&bus, 1);
+    // Listens to the raw packets without acknowledging anything
+    let listener = bus.connect(9);
+
+    let (report_tx, report_rx) = cbc::unbounded::<DeliveryReport>();
+    sender.network_unit.send_reliable(order_to(9, 1), &report_tx);
+    let mut copies: Vec<(Instant, u64)> = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while report_rx.is_empty() && Instant::now() < deadline {
+        if let Some(data) = listener.receive(Duration::from_millis(10)).unwrap() {
+            if let Ok(Packet::Data { seq, message, .. }) = serde_json::from_slice::<Packet>(&data) {
+                if message.target == Target::Unit(9) {
+                    copies.push((Instant::now(), seq));
+                }
+            }
+        }
+    }
+    let (_, delivered) = report_rx.try_recv().unwrap();
+    assert!(!delivered);
+
+    // Every copy has the same sequence number, and the sender gave up after ten of them
+    assert_eq!(copies.len(), 10);
+    assert!(copies.iter().all(|(_, seq)| *seq == copies[0].1));
+    // The wait between two copies never gets shorter, and has grown well past the first one at the end
+    let gaps: Vec<Duration> = copies.windows(2).map(|pair| pair[1].0 - pair[0].0).collect();
+    for pair in gaps.windows(2) {
+        assert!(pair[1] + Duration::from_millis(50) >= pair[0], "Resent sooner than before: {:?}", gaps);
+    }
+    assert!(gaps[gaps.len() - 1] > gaps[0] * 4, "No backoff: {:?}", gaps);
+
+    sender.network_unit.stop();
+}
@@ -0,0 +1,28 @@
+use std::path::{Path, PathBuf};
+use ttk4145_project::scenario::Scenario;
+
+fn scenario_dir() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("scenarios");
+}
+
+// Runs every file in tests/scenarios, one at a time so they don't slow each other down,
+// and reports every scenario that failed by its file name
+#[test]
+fn scenarios() {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(scenario_dir()).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "No scenarios in {}", scenario_dir().display());
+
+    let mut failures: Vec<String> = Vec::new();
+    for path in &files {
+        let file = path.file_name().unwrap().to_string_lossy().into_owned();
+        let result = Scenario::load(path).and_then(|scenario| scenario.run());
+        if let Err(e) = result {
+            failures.push(format!("{}: {}", file, e));
+        }
+    }
+    assert!(failures.is_empty(), "{} of {} scenarios failed:\n{}", failures.len(), files.len(), failures.join("\n\n"));
+}
@@ -0,0 +1,223 @@
+use ttk4145_project::prelude::*;
+use ttk4145_project::client::state_machine::*;
+
+const DOOR_TIME: Duration = Duration::from_millis(3000);
+const MOTOR_TIMEOUT: Duration = Duration::from_millis(10000);
+// Longer than the door takes to close after its timer runs out
+const CLOSING: Duration = Duration::from_millis(300);
+
+// An elevator that has found floor 0 after starting, and stands idle there
+fn idle_at_bottom(now: Instant) -> ElevatorStateMachine {
+    let mut state_machine = ElevatorStateMachine::new(4, DOOR_TIME, MOTOR_TIMEOUT, now);
+    let actions = state_machine.start(HashSet::new(), None, now);
+    assert_eq!(actions, vec![ElevatorAction::Motor(e::DIRN_DOWN)]);
+    state_machine.handle(ElevatorEvent::Floor(0), now);
+    assert_eq!(state_machine.behaviour, Behaviour::Idle);
+    assert_eq!(state_machine.motor, e::DIRN_STOP);
+    return state_machine;
+}
+
+// Runs the door timer until it has closed
+fn close_door(state_machine: &mut ElevatorStateMachine, opened: Instant) -> Vec<ElevatorAction> {
+    let mut actions = state_machine.handle(ElevatorEvent::Tick, opened + DOOR_TIME);
+    actions.extend(state_machine.handle(ElevatorEvent::Tick, opened + DOOR_TIME + CLOSING));
+    return actions;
+}
+
+#[test]
+fn cab_call_is_served_and_the_door_closes() {
+    let start = Instant::now();
+    let mut state_machine = idle_at_bottom(start);
+
+    let actions = state_machine.handle(ElevatorEvent::CabCall(2), start);
+    assert!(actions.contains(&ElevatorAction::SaveCabOrders));
+    assert!(actions.contains(&ElevatorAction::CabLight(2, true)));
+    assert!(actions.contains(&ElevatorAction::Motor(e::DIRN_UP)));
+
+    let actions = state_machine.handle(ElevatorEvent::Floor(1), start);
+    assert_eq!(actions, vec![ElevatorAction::FloorIndicator(1)]);
+
+    let arrived = start + Duration::from_secs(2);
+    let actions = state_machine.handle(ElevatorEvent::Floor(2), arrived);
+    assert_eq!(actions, vec![
+        ElevatorAction::FloorIndicator(2),
+        ElevatorAction::Motor(e::DIRN_STOP),
+        ElevatorAction::CabLight(2, false),
+        ElevatorAction::SaveCabOrders,
+        ElevatorAction::DoorLight(true),
+    ]);
+    assert_eq!(state_machine.behaviour, Behaviour::DoorOpen);
+    assert!(state_machine.cab_orders().is_empty());
+
+    assert!(state_machine.handle(ElevatorEvent::Tick, arrived + DOOR_TIME / 2).is_empty());
+    let actions = close_door(&mut state_machine, arrived);
+    assert_eq!(actions, vec![ElevatorAction::DoorLight(false)]);
+    assert_eq!(state_machine.behaviour, Behaviour::Idle);
+}
+
+#[test]
+fn route_goes_on_in_the_same_direction_before_turning() {
+    let start = Instant::now();
+    let mut state_machine = idle_at_bottom(start);
+    let hall_down = Order { floor_number: 2, call: CallKind::HallDown };
+
+    let actions = state_machine.handle(ElevatorEvent::OrderAssigned(hall_down), start);
+    assert!(actions.contains(&ElevatorAction::AckOrder(hall_down)));
+    state_machine.handle(ElevatorEvent::CabCall(3), start);
+
+    // Passes the call going down on the way up
+    state_machine.handle(ElevatorEvent::Floor(1), start);
+    let actions = state_machine.handle(ElevatorEvent::Floor(2), start);
+    assert!(!actions.contains(&ElevatorAction::Motor(e::DIRN_STOP)));
+
+    let actions = state_machine.handle(ElevatorEvent::Floor(3), start);
+    assert!(actions.contains(&ElevatorAction::DoorLight(true)));
+    let actions = close_door(&mut state_machine, start);
+    assert!(actions.contains(&ElevatorAction::Motor(e::DIRN_DOWN)));
+
+    // And takes it on the way down
+    let arrived = start + Duration::from_secs(10);
+    let actions = state_machine.handle(ElevatorEvent::Floor(2), arrived);
+    assert!(actions.contains(&ElevatorAction::ClearHallCall(hall_down)));
+    assert!(actions.contains(&ElevatorAction::DoorLight(true)));
+    close_door(&mut state_machine, arrived);
+    assert_eq!(state_machine.behaviour, Behaviour::Idle);
+    assert!(state_machine.destinations.is_empty());
+}
+
+#[test]
+fn obstruction_keeps_the_door_open_and_the_motor_off() {
+    let start = Instant::now();
+    let mut state_machine = idle_at_bottom(start);
+    let hall_up = Order { floor_number: 0, call: CallKind::HallUp };
+
+    let actions = state_machine.handle(ElevatorEvent::OrderAssigned(hall_up), start);
+    assert!(actions.contains(&ElevatorAction::DoorLight(true)));
+    state_machine.handle(ElevatorEvent::Obstruction(true), start);
+    state_machine.handle(ElevatorEvent::CabCall(3), start);
+
+    let mut now = start;
+    while now < start + 3 * DOOR_TIME {
+        now += Duration::from_millis(100);
+        let actions = state_machine.handle(ElevatorEvent::Tick, now);
+        assert!(actions.is_empty(), "{:?}", actions);
+    }
+    assert!(state_machine.obstructed);
+    assert!(!state_machine.status().available());
+
+    state_machine.handle(ElevatorEvent::Obstruction(false), now);
+    let actions = close_door(&mut state_machine, now);
+    assert_eq!(actions, vec![ElevatorAction::DoorLight(false), ElevatorAction::Motor(e::DIRN_UP)]);
+    assert!(!state_machine.obstructed);
+}
+
+#[test]
+fn motor_timeout_is_a_fault_until_a_floor_is_reached() {
+    let start = Instant::now();
+    let mut state_machine = idle_at_bottom(start);
+    let hall_up = Order { floor_number: 2, call: CallKind::HallUp };
+
+    state_machine.handle(ElevatorEvent::CabCall(3), start);
+    state_machine.handle(ElevatorEvent::OrderAssigned(hall_up), start);
+    state_machine.handle(ElevatorEvent::Tick, start + MOTOR_TIMEOUT);
+    assert_eq!(state_machine.behaviour, Behaviour::Fault(ElevatorError::MotorTimeout));
+    assert_eq!(state_machine.status().error, Some(ElevatorError::MotorTimeout));
+    assert_eq!(state_machine.motor, e::DIRN_UP);
+
+    // The hall order is left to the others, and new ones are not taken
+    assert!(!state_machine.destinations.contains(&hall_up));
+    let actions = state_machine.handle(ElevatorEvent::OrderAssigned(hall_up), start + MOTOR_TIMEOUT);
+    assert!(actions.is_empty());
+
+    state_machine.handle(ElevatorEvent::Floor(1), start + 2 * MOTOR_TIMEOUT);
+    assert_eq!(state_machine.behaviour, Behaviour::Moving);
+    assert!(state_machine.status().available());
+}
+
+// A jump to the top floor stops the car there. With no order to go on with, it heads down to clear the fault.
+#[test]
+fn floor_jump_at_the_end_of_the_shaft_recovers() {
+    let start = Instant::now();
+    let mut state_machine = idle_at_bottom(start);
+    state_machine.handle(ElevatorEvent::CabCall(3), start);
+
+    let actions = state_machine.handle(ElevatorEvent::Floor(3), start);
+    assert_eq!(state_machine.behaviour, Behaviour::Fault(ElevatorError::FloorJump));
+    assert!(actions.contains(&ElevatorAction::Motor(e::DIRN_STOP)));
+
+    let actions = state_machine.handle(ElevatorEvent::Tick, start);
+    assert_eq!(actions, vec![ElevatorAction::Motor(e::DIRN_DOWN)]);
+
+    // The next floor is trusted, and the car goes back up for its cab order
+    let actions = state_machine.handle(ElevatorEvent::Floor(2), start + Duration::from_secs(1));
+    assert!(state_machine.status().error.is_none());
+    assert!(actions.contains(&ElevatorAction::Motor(e::DIRN_UP)));
+    let actions = state_machine.handle(ElevatorEvent::Floor(3), start + Duration::from_secs(2));
+    assert!(actions.contains(&ElevatorAction::DoorLight(true)));
+}
+
+// The car may have been moved while the hardware was gone, so the first floor after connecting again is not a jump
+#[test]
+fn floor_after_reconnecting_is_not_a_jump() {
+    let start = Instant::now();
+    let mut state_machine = idle_at_bottom(start);
+
+    state_machine.handle(ElevatorEvent::DriverFailure(true), start);
+    assert_eq!(state_machine.status().error, Some(ElevatorError::DriverFailure));
+    state_machine.handle(ElevatorEvent::DriverFailure(false), start);
+    state_machine.handle(ElevatorEvent::Floor(2), start);
+    assert_eq!(state_machine.behaviour, Behaviour::Idle);
+    assert_eq!(state_machine.floor, Some(2));
+    assert!(state_machine.status().error.is_none());
+}
+
+#[test]
+fn stop_button_between_floors_holds_and_resumes() {
+    let start = Instant::now();
+    let mut state_machine = idle_at_bottom(start);
+
+    state_machine.handle(ElevatorEvent::CabCall(3), start);
+    let actions = state_machine.handle(ElevatorEvent::StopButton { pressed: true, at_floor: false }, start);
+    assert_eq!(actions, vec![ElevatorAction::Motor(e::DIRN_STOP), ElevatorAction::StopLight(true)]);
+    assert!(state_machine.status().stopped);
+
+    // Nothing moves while the button is pressed, and the cab order is kept
+    let actions = state_machine.handle(ElevatorEvent::Tick, start + MOTOR_TIMEOUT * 2);
+    assert!(actions.is_empty());
+    assert_eq!(state_machine.cab_orders().len(), 1);
+
+    let actions = state_machine.handle(ElevatorEvent::StopButton { pressed: false, at_floor: false }, start + MOTOR_TIMEOUT * 2);
+    assert_eq!(actions, vec![ElevatorAction::StopLight(false), ElevatorAction::Motor(e::DIRN_UP)]);
+    assert_eq!(state_machine.behaviour, Behaviour::Moving);
+}
+
+#[test]
+fn cab_order_stops_the_elevator_whichever_way_it_goes() {
+    let start = Instant::now();
+    let mut state_machine = idle_at_bottom(start);
+    let hall_down = Order { floor_number: 3, call: CallKind::HallDown };
+    let cab_order = Order { floor_number: 1, call: CallKind::Cab };
+
+    state_machine.handle(ElevatorEvent::OrderAssigned(hall_down), start);
+    state_machine.handle(ElevatorEvent::Floor(1), start);
+    // Pressed just as we pass the floor on the way up, so it is served on the way down
+    state_machine.handle(ElevatorEvent::CabCall(1), start);
+    assert!(state_machine.destinations.contains(&cab_order));
+    assert_eq!(state_machine.status().destinations.cab, 1 << 1);
+
+    state_machine.handle(ElevatorEvent::Floor(2), start);
+    let actions = state_machine.handle(ElevatorEvent::Floor(3), start);
+    assert!(actions.contains(&ElevatorAction::ClearHallCall(hall_down)));
+    assert!(!actions.contains(&ElevatorAction::CabLight(3, false)));
+    state_machine.handle(ElevatorEvent::CabCall(0), start);
+    close_door(&mut state_machine, start);
+
+    let actions = state_machine.handle(ElevatorEvent::Floor(2), start);
+    assert!(!actions.contains(&ElevatorAction::Motor(e::DIRN_STOP)));
+    let arrived = start + Duration::from_secs(10);
+    let actions = state_machine.handle(ElevatorEvent::Floor(1), arrived);
+    assert!(actions.contains(&ElevatorAction::Motor(e::DIRN_STOP)));
+    assert!(actions.contains(&ElevatorAction::CabLight(1, false)));
+    assert!(!actions.iter().any(|action| matches!(action, ElevatorAction::ClearHallCall(_))));
+    assert_eq!(state_machine.cab_orders(), HashSet::from([Order { floor_number: 0, call: CallKind::Cab }]));
+}
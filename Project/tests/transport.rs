@@ -0,0 +1,20 @@
+use ttk4145_project::prelude::*;
+use ttk4145_project::network::transport::*;
+
+// Units on the same host all listen on the default port, so each of them has to get every broadcast
+#[test]
+fn two_units_listen_on_one_port() {
+    let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let listen_addr = format!("0.0.0.0:{}", port);
+    let broadcast_addr = format!("127.255.255.255:{}", port);
+    let first = UdpTransport::new(&listen_addr, &broadcast_addr);
+    let second = UdpTransport::new(&listen_addr, &broadcast_addr);
+
+    // The receive sockets are bound on first use
+    assert_eq!(first.receive(Duration::from_millis(10)).unwrap(), None);
+    assert_eq!(second.receive(Duration::from_millis(10)).unwrap(), None);
+
+    first.broadcast(b"hello").unwrap();
+    assert_eq!(first.receive(Duration::from_secs(1)).unwrap(), Some(b"hello".to_vec()));
+    assert_eq!(second.receive(Duration::from_secs(1)).unwrap(), Some(b"hello".to_vec()));
+}